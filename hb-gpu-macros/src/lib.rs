@@ -0,0 +1,360 @@
+//! Macros for hb-gpu, use them through the re-exports in `hb_gpu::bindgroup` and `hb_gpu::bundle`
+
+// shared with the runtime so bundles are flattened and validated exactly like files loaded at runtime
+use hb_gpu_shader::{codegen, diagnostics, preprocess};
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse::{Parse, ParseStream}, spanned::Spanned, Data, DeriveInput, Error, Fields, Ident, LitInt, LitStr, Token};
+
+/// Implement `AsBindGroup` for a struct whose fields are resources
+///
+/// Each bound field takes one attribute with its binding index first, then options:
+///
+/// - `#[uniform(N)]` on a `Buffer`, `ty = "Camera"` sets the WGSL type (the field name in PascalCase by default)
+/// - `#[storage(N)]` on a `Buffer`, add `read_only` for `var<storage, read>`, `ty` works like for uniforms
+/// - `#[texture(N)]` on a `Texture`, with `dim = "2d" | "2d_array" | "cube" | "cube_array" | "3d" | "1d"`,
+///   `sample = "f32" | "i32" | "u32" | "depth"`, and the flags `unfilterable` and `multisampled`
+/// - `#[sampler(N)]` on a `Sampler`, with the flags `comparison` or `non_filtering`
+///
+/// Any of them can list the stages that see the binding with the `vertex`, `fragment` and `compute` flags. The default
+/// is all three, except writable storage buffers which default to fragment and compute.
+/// Fields can also be references to these types, fields without an attribute are ignored
+#[proc_macro_derive(BindGroup, attributes(uniform, storage, texture, sampler))]
+pub fn derive_bind_group(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Arguments of one binding attribute: an index, then `flag` or `key = "value"` options
+struct BindingArgs {
+    index: u32,
+    flags: Vec<Ident>,
+    values: Vec<(Ident, LitStr)>,
+}
+
+impl Parse for BindingArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let index: LitInt = input.parse()?;
+        let mut args = BindingArgs {
+            index: index.base10_parse()?,
+            flags: Vec::new(),
+            values: Vec::new(),
+        };
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.parse()?;
+            if input.peek(Token![=]) {
+                input.parse::<Token![=]>()?;
+                args.values.push((key, input.parse()?));
+            } else {
+                args.flags.push(key);
+            }
+        }
+        Ok(args)
+    }
+}
+
+impl BindingArgs {
+    fn check(&self, flags: &[&str], values: &[&str]) -> syn::Result<()> {
+        for flag in &self.flags {
+            let name = flag.to_string();
+            if !flags.contains(&name.as_str()) && !["vertex", "fragment", "compute"].contains(&name.as_str()) {
+                return Err(Error::new(flag.span(), format!("unknown option `{name}`")));
+            }
+        }
+        for (key, _) in &self.values {
+            if !values.contains(&key.to_string().as_str()) {
+                return Err(Error::new(key.span(), format!("unknown option `{key} = ...`")));
+            }
+        }
+        Ok(())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&LitStr> {
+        self.values.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+    }
+
+    fn stages(&self, default: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let stages: Vec<_> = ["vertex", "fragment", "compute"].iter()
+            .filter(|s| self.flag(s))
+            .map(|s| format_ident!("{}", s.to_uppercase()))
+            .collect();
+        if stages.is_empty() {
+            default
+        } else {
+            quote!(#(::hb_gpu::prelude::wgpu::ShaderStages::#stages)|*)
+        }
+    }
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(input.span(), "#[derive(BindGroup)] only works on structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(input.span(), "#[derive(BindGroup)] needs a struct with named fields"));
+    };
+
+    let wgpu = quote!(::hb_gpu::prelude::wgpu);
+    let all_stages = quote!(#wgpu::ShaderStages::VERTEX_FRAGMENT | #wgpu::ShaderStages::COMPUTE);
+
+    let mut layout_entries = Vec::new();
+    let mut wgsl_lines = Vec::new();
+    let mut views = Vec::new();
+    let mut bindings = Vec::new();
+    let mut used: Vec<u32> = Vec::new();
+
+    for field in &fields.named {
+        let ident = field.ident.as_ref().expect("named field");
+        let name = ident.to_string().trim_start_matches("r#").to_string();
+
+        for attr in &field.attrs {
+            let Some(kind) = attr.path().get_ident().map(|i| i.to_string()) else { continue };
+            if !["uniform", "storage", "texture", "sampler"].contains(&kind.as_str()) {
+                continue;
+            }
+
+            let args: BindingArgs = attr.parse_args()?;
+            let index = args.index;
+            if used.contains(&index) {
+                return Err(Error::new_spanned(attr, format!("binding {index} is used by more than one field")));
+            }
+            used.push(index);
+
+            let (ty, stages) = match kind.as_str() {
+                "uniform" | "storage" => {
+                    let read_only = kind == "storage" && args.flag("read_only");
+                    if kind == "uniform" {
+                        args.check(&[], &["ty"])?;
+                    } else {
+                        args.check(&["read_only"], &["ty"])?;
+                    }
+                    let wgsl_ty = args.value("ty").map(LitStr::value).unwrap_or_else(|| pascal_case(&name));
+
+                    let (buffer_ty, space, view) = match (kind.as_str(), read_only) {
+                        ("uniform", _) => (quote!(#wgpu::BufferBindingType::Uniform), "uniform", quote!(self.#ident.view_all())),
+                        (_, true) => (
+                            quote!(#wgpu::BufferBindingType::Storage { read_only: true }),
+                            "storage, read",
+                            quote!(self.#ident.view_read(0, self.#ident.raw.size())),
+                        ),
+                        _ => (
+                            quote!(#wgpu::BufferBindingType::Storage { read_only: false }),
+                            "storage, read_write",
+                            quote!(self.#ident.view_all()),
+                        ),
+                    };
+                    wgsl_lines.push(format!("@group({{group}}) @binding({index}) var<{space}> {name}: {wgsl_ty};"));
+
+                    let view_ident = format_ident!("__{}_view", name);
+                    views.push(quote!(let #view_ident = #view;));
+                    bindings.push((index, quote!(with_buffer(&#view_ident, __stages))));
+
+                    let default_stages = if kind == "storage" && !read_only {
+                        quote!(#wgpu::ShaderStages::FRAGMENT | #wgpu::ShaderStages::COMPUTE)
+                    } else {
+                        all_stages.clone()
+                    };
+                    let ty = quote!(#wgpu::BindingType::Buffer { ty: #buffer_ty, has_dynamic_offset: false, min_binding_size: None });
+                    (ty, args.stages(default_stages))
+                },
+                "texture" => {
+                    args.check(&["unfilterable", "multisampled"], &["dim", "sample"])?;
+                    let multisampled = args.flag("multisampled");
+
+                    let dim_lit = args.value("dim").cloned().unwrap_or_else(|| LitStr::new("2d", Span::call_site()));
+                    let dim = match dim_lit.value().as_str() {
+                        "1d" => quote!(D1),
+                        "2d" => quote!(D2),
+                        "2d_array" => quote!(D2Array),
+                        "cube" => quote!(Cube),
+                        "cube_array" => quote!(CubeArray),
+                        "3d" => quote!(D3),
+                        other => return Err(Error::new(dim_lit.span(), format!("unknown texture dimension `{other}`"))),
+                    };
+
+                    let sample_lit = args.value("sample").cloned().unwrap_or_else(|| LitStr::new("f32", Span::call_site()));
+                    let sample = sample_lit.value();
+                    let sample_type = match sample.as_str() {
+                        "f32" => {
+                            // multisampled textures can't be filtered
+                            let filterable = !args.flag("unfilterable") && !multisampled;
+                            quote!(#wgpu::TextureSampleType::Float { filterable: #filterable })
+                        },
+                        "i32" => quote!(#wgpu::TextureSampleType::Sint),
+                        "u32" => quote!(#wgpu::TextureSampleType::Uint),
+                        "depth" => quote!(#wgpu::TextureSampleType::Depth),
+                        other => return Err(Error::new(sample_lit.span(), format!("unknown sample type `{other}`"))),
+                    };
+
+                    let multi = if multisampled { "multisampled_" } else { "" };
+                    let wgsl_ty = if sample == "depth" {
+                        format!("texture_depth_{multi}{}", dim_lit.value())
+                    } else {
+                        format!("texture_{multi}{}<{sample}>", dim_lit.value())
+                    };
+                    wgsl_lines.push(format!("@group({{group}}) @binding({index}) var {name}: {wgsl_ty};"));
+                    bindings.push((index, quote!(with_texture(&self.#ident, __stages))));
+
+                    let ty = quote!(#wgpu::BindingType::Texture {
+                        sample_type: #sample_type,
+                        view_dimension: #wgpu::TextureViewDimension::#dim,
+                        multisampled: #multisampled,
+                    });
+                    (ty, args.stages(all_stages.clone()))
+                },
+                _ => {
+                    args.check(&["comparison", "non_filtering"], &[])?;
+                    let (sampler_ty, wgsl_ty) = if args.flag("comparison") {
+                        (quote!(Comparison), "sampler_comparison")
+                    } else if args.flag("non_filtering") {
+                        (quote!(NonFiltering), "sampler")
+                    } else {
+                        (quote!(Filtering), "sampler")
+                    };
+                    wgsl_lines.push(format!("@group({{group}}) @binding({index}) var {name}: {wgsl_ty};"));
+                    bindings.push((index, quote!(with_sampler(&self.#ident, __stages))));
+
+                    let ty = quote!(#wgpu::BindingType::Sampler(#wgpu::SamplerBindingType::#sampler_ty));
+                    (ty, args.stages(all_stages.clone()))
+                },
+            };
+
+            layout_entries.push(quote! {
+                #wgpu::BindGroupLayoutEntry {
+                    binding: #index,
+                    visibility: #stages,
+                    ty: #ty,
+                    count: None,
+                }
+            });
+            // the builder needs the stages too, keep them next to the call
+            let (_, call) = bindings.pop().expect("binding was just pushed");
+            bindings.push((index, quote!({ let __stages = #stages; builder.binding(#index).#call; })));
+        }
+    }
+
+    let name = &input.ident;
+    let label = name.to_string();
+    let wgsl = wgsl_lines.join("\n") + "\n";
+    let calls = bindings.iter().map(|(_, call)| call);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::hb_gpu::bindgroup::AsBindGroup for #name #ty_generics #where_clause {
+            fn layout_entries() -> ::hb_gpu::bindgroup::BindGroupLayoutEntries {
+                ::hb_gpu::bindgroup::BindGroupLayoutEntries::new(::std::vec![#(#layout_entries),*])
+            }
+
+            fn wgsl(group: u32) -> ::std::string::String {
+                #wgsl.replace("{group}", &group.to_string())
+            }
+
+            fn bind_group(&self, gpu: &::hb_gpu::gpu::Gpu, resources: &mut ::hb_gpu::resource::ResourceManager)
+                -> ::std::result::Result<::hb_gpu::bindgroup::BindGroup, ::hb_gpu::bindgroup::BindGroupError> {
+                #(#views)*
+                let mut builder = gpu.new_bind_group();
+                builder.with_label(#label);
+                #(#calls)*
+                builder.check_layout(&<Self as ::hb_gpu::bindgroup::AsBindGroup>::layout_entries())?;
+                builder.finish(resources)
+            }
+        }
+    })
+}
+
+/// Flatten a WGSL file's `@import`s at build time, validate it with naga and embed the result as a `ShaderBundle`
+///
+/// The path is relative to the crate's `Cargo.toml`. Shader errors fail the build with the same diagnostics as
+/// runtime loading, and editing any file in the bundle triggers a rebuild
+#[proc_macro]
+pub fn include_wgsl_bundle(input: TokenStream) -> TokenStream {
+    let path = syn::parse_macro_input!(input as LitStr);
+    match expand_bundle(&path) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_bundle(path: &LitStr) -> syn::Result<proc_macro2::TokenStream> {
+    let (root, shader, _) = load_shader(path)?;
+    let files: Vec<&str> = shader.graph.files().collect();
+    let code = &shader.code;
+    Ok(quote! {{
+        // makes cargo rebuild when any of the files change
+        #(const _: &[u8] = include_bytes!(#files);)*
+        ::hb_gpu::bundle::ShaderBundle {
+            path: #root,
+            code: #code,
+            files: &[#(#files),*],
+        }
+    }})
+}
+
+/// Generate `#[repr(C)]` `bytemuck::Pod` structs with the WGSL layout for the structs a shader's buffers use, plus
+/// `<NAME>_GROUP` and `<NAME>_BINDING` constants for its bindings, see `hb_gpu::codegen::generate_rust`
+///
+/// The path is relative to the crate's `Cargo.toml` and `@import`s are followed. The items are expanded in place, so
+/// wrap the call in a module to keep them apart from other shaders'
+#[proc_macro]
+pub fn include_wgsl_structs(input: TokenStream) -> TokenStream {
+    let path = syn::parse_macro_input!(input as LitStr);
+    match expand_structs(&path) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_structs(path: &LitStr) -> syn::Result<proc_macro2::TokenStream> {
+    let (_, shader, module) = load_shader(path)?;
+    let items: proc_macro2::TokenStream = codegen::generate_rust(&module)
+        .map_err(|e| Error::new(path.span(), e))?
+        .parse()
+        .map_err(|e| Error::new(path.span(), format!("generated invalid Rust: {e}")))?;
+    let files: Vec<&str> = shader.graph.files().collect();
+    Ok(quote! {
+        #(const _: &[u8] = include_bytes!(#files);)*
+        #items
+    })
+}
+
+/// Preprocess and validate the file at `path`, relative to the calling crate
+fn load_shader(path: &LitStr) -> syn::Result<(String, preprocess::PreprocessedShader, naga::Module)> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").map_err(|e| Error::new(path.span(), e))?;
+    let root = preprocess::normalize_path(&format!("{manifest_dir}/{}", path.value()));
+
+    let shader = preprocess::preprocess(&root, |file| std::fs::read_to_string(file).map_err(|e| e.to_string()))
+        .map_err(|e| shader_error(path, &diagnostics::Diagnostic::from(&e)))?;
+    let (module, _) = diagnostics::validate_wgsl(&shader)
+        .map_err(|d| shader_error(path, &d))?;
+    Ok((root, shader, module))
+}
+
+fn shader_error(path: &LitStr, diagnostic: &diagnostics::Diagnostic) -> Error {
+    // rustc adds its own `error: ` in front
+    let rendered = diagnostic.render();
+    Error::new(path.span(), rendered.strip_prefix("error: ").unwrap_or(&rendered))
+}
@@ -0,0 +1,310 @@
+//! Rust structs generated from WGSL struct declarations
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+
+use naga::common::wgsl::TypeContext;
+
+/// A type used by a buffer binding that has no Rust equivalent
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenError {
+    /// The WGSL type
+    pub ty: String,
+    pub reason: String,
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "can't generate a Rust type for `{}`: {}", self.ty, self.reason)
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+/// Rust source mirroring the structs `module`'s uniform, storage and push constant variables use, with the WGSL layout
+///
+/// Each struct is `#[repr(C)]` with explicit `_padN` fields wherever WGSL leaves a gap, implements `bytemuck::Pod`
+/// through `hb_gpu`'s re-export and `Default` as all zeroes, so `..Default::default()` fills the padding. Vectors
+/// and matrices become arrays, `vec3` in arrays and the columns of `matNx3` are padded to 4 elements and `f16` is
+/// stored as its bits in a `u16`. A struct ending in a runtime-sized array stops before it.
+///
+/// Every variable with a binding also gets `<NAME>_GROUP` and `<NAME>_BINDING` constants
+pub fn generate_rust(module: &naga::Module) -> Result<String, CodegenError> {
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(module.to_ctx()).map_err(|e| CodegenError { ty: String::new(), reason: e.to_string() })?;
+    let generator = Generator { module, layouter };
+
+    let mut buffer_types = BTreeSet::new();
+    for (_, var) in module.global_variables.iter() {
+        if matches!(var.space, naga::AddressSpace::Uniform | naga::AddressSpace::Storage { .. } | naga::AddressSpace::PushConstant) {
+            generator.collect_structs(var.ty, &mut buffer_types);
+        }
+    }
+
+    let mut out = String::new();
+    // the arena puts types before the structs that use them
+    for handle in buffer_types {
+        generator.write_struct(&mut out, handle)?;
+    }
+
+    for (_, var) in module.global_variables.iter() {
+        if let (Some(binding), Some(name)) = (&var.binding, &var.name) {
+            let name = screaming_snake_case(name);
+            let _ = writeln!(out, "pub const {name}_GROUP: u32 = {};", binding.group);
+            let _ = writeln!(out, "pub const {name}_BINDING: u32 = {};", binding.binding);
+        }
+    }
+    Ok(out)
+}
+
+struct Generator<'a> {
+    module: &'a naga::Module,
+    layouter: naga::proc::Layouter,
+}
+
+impl Generator<'_> {
+    fn collect_structs(&self, ty: naga::Handle<naga::Type>, structs: &mut BTreeSet<naga::Handle<naga::Type>>) {
+        match &self.module.types[ty].inner {
+            // the insert skips structs that were already visited
+            naga::TypeInner::Struct { members, .. } if structs.insert(ty) => {
+                for member in members {
+                    self.collect_structs(member.ty, structs);
+                }
+            },
+            naga::TypeInner::Array { base, .. } | naga::TypeInner::BindingArray { base, .. } => self.collect_structs(*base, structs),
+            _ => {},
+        }
+    }
+
+    fn error(&self, ty: naga::Handle<naga::Type>, reason: &str) -> CodegenError {
+        CodegenError { ty: self.module.to_ctx().type_to_string(ty), reason: reason.to_string() }
+    }
+
+    fn write_struct(&self, out: &mut String, handle: naga::Handle<naga::Type>) -> Result<(), CodegenError> {
+        let ty = &self.module.types[handle];
+        let naga::TypeInner::Struct { members, span } = &ty.inner else {
+            unreachable!("only structs are collected");
+        };
+        let name = rust_ident(ty.name.as_deref().ok_or_else(|| self.error(handle, "the struct has no name"))?);
+
+        let mut fields = String::new();
+        let mut offset = 0;
+        let mut padding = 0;
+        let mut size = *span;
+        let mut tail = None;
+        for member in members {
+            if member.offset > offset {
+                let _ = writeln!(fields, "    pub _pad{padding}: [u8; {}],", member.offset - offset);
+                padding += 1;
+                offset = member.offset;
+            }
+
+            if let naga::TypeInner::Array { base, size: naga::ArraySize::Dynamic, .. } = self.module.types[member.ty].inner {
+                size = member.offset;
+                tail = Some(self.module.to_ctx().type_to_string(base));
+                break;
+            }
+            let member_name = rust_ident(member.name.as_deref().unwrap_or("_"));
+            let _ = writeln!(fields, "    pub {member_name}: {},", self.rust_type(member.ty)?);
+            offset = member.offset + self.layouter[member.ty].size;
+        }
+        if size > offset {
+            let _ = writeln!(fields, "    pub _pad{padding}: [u8; {}],", size - offset);
+        }
+
+        let wgsl_name = ty.name.as_deref().unwrap_or_default();
+        let _ = writeln!(out, "/// WGSL `{wgsl_name}`, {size} bytes");
+        if let Some(tail) = tail {
+            let _ = writeln!(out, "///\n/// The runtime-sized `array<{tail}>` that follows it in WGSL isn't included");
+        }
+        let _ = writeln!(out, "#[repr(C)]\n#[derive(Clone, Copy, Debug, PartialEq)]\n#[allow(non_camel_case_types, non_snake_case)]");
+        let _ = write!(out, "pub struct {name} {{\n{fields}}}\n\n");
+
+        // sound because the assert below proves there is no padding the compiler added, every field is Pod
+        let _ = writeln!(out, "unsafe impl ::hb_gpu::prelude::bytemuck::Zeroable for {name} {{}}");
+        let _ = writeln!(out, "unsafe impl ::hb_gpu::prelude::bytemuck::Pod for {name} {{}}");
+        let _ = writeln!(out, "const _: () = assert!(::core::mem::size_of::<{name}>() == {size});\n");
+        let _ = writeln!(out, "impl Default for {name} {{\n    fn default() -> Self {{\n        ::hb_gpu::prelude::bytemuck::Zeroable::zeroed()\n    }}\n}}\n");
+        Ok(())
+    }
+
+    fn rust_type(&self, ty: naga::Handle<naga::Type>) -> Result<String, CodegenError> {
+        let scalar = |scalar: naga::Scalar| -> Result<&'static str, CodegenError> {
+            Ok(match (scalar.kind, scalar.width) {
+                (naga::ScalarKind::Float, 2) => "u16",
+                (naga::ScalarKind::Float, 4) => "f32",
+                (naga::ScalarKind::Float, 8) => "f64",
+                (naga::ScalarKind::Sint, 4) => "i32",
+                (naga::ScalarKind::Sint, 8) => "i64",
+                (naga::ScalarKind::Uint, 4) => "u32",
+                (naga::ScalarKind::Uint, 8) => "u64",
+                _ => return Err(self.error(ty, "the scalar type isn't host-shareable")),
+            })
+        };
+
+        match &self.module.types[ty].inner {
+            naga::TypeInner::Scalar(s) | naga::TypeInner::Atomic(s) => Ok(scalar(*s)?.to_string()),
+            naga::TypeInner::Vector { size, scalar: s } => Ok(format!("[{}; {}]", scalar(*s)?, *size as u8)),
+            naga::TypeInner::Matrix { columns, rows, scalar: s } => {
+                // columns are aligned like vectors, so a vec3 column takes the space of a vec4
+                let rows = if *rows == naga::VectorSize::Tri { 4 } else { *rows as u8 };
+                Ok(format!("[[{}; {rows}]; {}]", scalar(*s)?, *columns as u8))
+            },
+            naga::TypeInner::Array { base, size, stride } => {
+                let naga::ArraySize::Constant(count) = size else {
+                    return Err(self.error(ty, "only fixed-size arrays, or a runtime-sized array at the end of a struct, are supported"));
+                };
+                let element = match &self.module.types[*base].inner {
+                    _ if self.layouter[*base].size == *stride => self.rust_type(*base)?,
+                    naga::TypeInner::Vector { size: naga::VectorSize::Tri, scalar: s } if *stride == 4 * s.width as u32 => format!("[{}; 4]", scalar(*s)?),
+                    _ => return Err(self.error(ty, "the array stride doesn't match its element")),
+                };
+                Ok(format!("[{element}; {count}]"))
+            },
+            naga::TypeInner::Struct { .. } => {
+                let name = self.module.types[ty].name.as_deref().ok_or_else(|| self.error(ty, "the struct has no name"))?;
+                Ok(rust_ident(name))
+            },
+            _ => Err(self.error(ty, "it can't be stored in a buffer")),
+        }
+    }
+}
+
+/// `name` escaped if it's a Rust keyword
+fn rust_ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern", "false", "fn", "for",
+        "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+        "trait", "true", "try", "type", "unsafe", "use", "where", "while", "yield",
+    ];
+    match name {
+        // these can't be raw identifiers
+        "crate" | "self" | "Self" | "super" => format!("{name}_"),
+        _ if KEYWORDS.contains(&name) => format!("r#{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// `camelCase` and `snake_case` names as `SCREAMING_SNAKE_CASE`
+fn screaming_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_uppercase() && previous_lower {
+            out.push('_');
+        }
+        previous_lower = c.is_lowercase() || c.is_ascii_digit();
+        out.extend(c.to_uppercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(source: &str) -> Result<String, CodegenError> {
+        generate_rust(&naga::front::wgsl::parse_str(source).unwrap())
+    }
+
+    /// The field lines of struct `name`, trimmed
+    fn fields(code: &str, name: &str) -> Vec<String> {
+        let start = code.find(&format!("pub struct {name} {{\n")).unwrap_or_else(|| panic!("no struct {name} in\n{code}"));
+        code[start..].lines().skip(1).take_while(|line| *line != "}").map(|line| line.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn pads_gaps_and_the_tail() {
+        let code = generate("
+            struct Light { position: vec3<f32>, intensity: f32, color: vec3<f32>, range: vec2<f32> }
+            @group(0) @binding(0) var<uniform> light: Light;
+        ").unwrap();
+
+        assert_eq!(fields(&code, "Light"), [
+            "pub position: [f32; 3],",
+            "pub intensity: f32,",
+            "pub color: [f32; 3],",
+            "pub _pad0: [u8; 4],",
+            "pub range: [f32; 2],",
+            "pub _pad1: [u8; 8],",
+        ]);
+        assert!(code.contains("/// WGSL `Light`, 48 bytes"));
+        assert!(code.contains("const _: () = assert!(::core::mem::size_of::<Light>() == 48);"));
+    }
+
+    #[test]
+    fn pads_vec3_array_elements() {
+        let code = generate("
+            struct Path { points: array<vec3<f32>, 4>, count: u32 }
+            @group(0) @binding(0) var<storage> path: Path;
+        ").unwrap();
+
+        assert_eq!(fields(&code, "Path"), ["pub points: [[f32; 4]; 4],", "pub count: u32,", "pub _pad0: [u8; 12],"]);
+    }
+
+    #[test]
+    fn pads_matrix_columns_with_three_rows() {
+        let code = generate("
+            struct Transforms { normal: mat3x3<f32>, skew: mat2x3<f32>, flat: mat3x2<f32>, model: mat4x4<f32> }
+            @group(0) @binding(0) var<uniform> transforms: Transforms;
+        ").unwrap();
+
+        assert_eq!(fields(&code, "Transforms"), [
+            "pub normal: [[f32; 4]; 3],",
+            "pub skew: [[f32; 4]; 2],",
+            "pub flat: [[f32; 2]; 3],",
+            "pub _pad0: [u8; 8],",
+            "pub model: [[f32; 4]; 4],",
+        ]);
+    }
+
+    #[test]
+    fn nested_structs_and_runtime_arrays() {
+        let code = generate("
+            struct Particle { position: vec2<f32>, age: f32 }
+            struct Particles { count: u32, items: array<Particle> }
+            @group(1) @binding(2) var<storage, read_write> particles: Particles;
+        ").unwrap();
+
+        assert_eq!(fields(&code, "Particle"), ["pub position: [f32; 2],", "pub age: f32,", "pub _pad0: [u8; 4],"]);
+        // the struct stops at the runtime-sized array, which starts at the element alignment
+        assert_eq!(fields(&code, "Particles"), ["pub count: u32,", "pub _pad0: [u8; 4],"]);
+        assert!(code.contains("The runtime-sized `array<Particle>` that follows it in WGSL isn't included"));
+        assert!(code.find("pub struct Particle ").unwrap() < code.find("pub struct Particles ").unwrap());
+        assert!(code.contains("pub const PARTICLES_GROUP: u32 = 1;\npub const PARTICLES_BINDING: u32 = 2;"));
+    }
+
+    #[test]
+    fn only_buffer_structs_are_generated() {
+        let code = generate("
+            struct Unused { x: f32 }
+            struct Params { gen: u32, self_: u32 }
+            @group(0) @binding(0) var<uniform> sceneParams: Params;
+            @group(0) @binding(1) var color: texture_2d<f32>;
+        ").unwrap();
+
+        assert!(!code.contains("Unused"));
+        assert_eq!(fields(&code, "Params"), ["pub r#gen: u32,", "pub self_: u32,"]);
+        assert!(code.contains("pub const SCENE_PARAMS_BINDING: u32 = 0;"));
+        assert!(code.contains("pub const COLOR_BINDING: u32 = 1;"));
+    }
+
+    #[test]
+    fn rejects_types_without_a_rust_equivalent() {
+        let error = generate("
+            struct Flags { enabled: bool }
+            var<push_constant> flags: Flags;
+        ").unwrap_err();
+        assert_eq!(error.ty, "bool");
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(rust_ident("type"), "r#type");
+        assert_eq!(rust_ident("self"), "self_");
+        assert_eq!(rust_ident("color"), "color");
+        assert_eq!(screaming_snake_case("cameraUniform2d"), "CAMERA_UNIFORM2D");
+        assert_eq!(screaming_snake_case("light_data"), "LIGHT_DATA");
+    }
+}
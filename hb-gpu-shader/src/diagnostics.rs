@@ -0,0 +1,250 @@
+use std::fmt;
+
+use crate::preprocess::{PreprocessError, PreprocessedShader, SourceMap};
+
+/// A span of one source line that a diagnostic points at, already mapped back to the file it was written in
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiagnosticLabel {
+    pub file: String,
+    /// 1-based line in `file`
+    pub line: usize,
+    /// 1-based column in characters
+    pub column: usize,
+    /// Length in characters, at least 1 and clipped to the end of the line
+    pub length: usize,
+    pub message: String,
+    /// The text of the line, `None` when the error didn't come with the source
+    pub source_line: Option<String>,
+    /// The `@import`s that pulled `file` in, outermost first, as (importing file, line of the import)
+    pub import_chain: Vec<(String, usize)>,
+}
+
+impl DiagnosticLabel {
+    /// Turn a span of the preprocessed code into a label in the original file, `None` if it doesn't map to one
+    pub fn from_span(code: &str, source_map: &SourceMap, span: naga::Span, message: &str) -> Option<Self> {
+        let range = span.to_range()?;
+        let start = range.start.min(code.len());
+        let line_start = code[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = code[start..].find('\n').map_or(code.len(), |i| start + i);
+        let line = code[..start].matches('\n').count() + 1;
+
+        let source_line = code[line_start..line_end].trim_end_matches('\r');
+        let column = code[line_start..start].chars().count() + 1;
+        let length = code[start..range.end.clamp(start, line_end)].chars().count().max(1);
+
+        let (file, line) = source_map.locate(line)?;
+        Some(Self {
+            file: file.to_string(),
+            line,
+            column,
+            length,
+            message: message.to_string(),
+            source_line: Some(source_line.to_string()),
+            import_chain: source_map.import_chain(file).to_vec(),
+        })
+    }
+}
+
+/// A shader error in a form that can be rendered in a terminal with `render` or shown by an in-app overlay
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Where the error is, the first label is the primary one. Can be empty for errors without a location
+    pub labels: Vec<DiagnosticLabel>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn from_parse_error(error: &naga::front::wgsl::ParseError, code: &str, source_map: &SourceMap) -> Self {
+        Self {
+            message: error.message().to_string(),
+            labels: error.labels()
+                .filter_map(|(span, message)| DiagnosticLabel::from_span(code, source_map, span, message))
+                .collect(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn from_validation_error(error: &naga::WithSpan<naga::valid::ValidationError>, code: &str, source_map: &SourceMap) -> Self {
+        // the useful part of a validation error is usually at the end of its source chain
+        let mut notes = Vec::new();
+        let mut source: &dyn std::error::Error = error.as_inner();
+        while let Some(next) = source.source() {
+            notes.push(next.to_string());
+            source = next;
+        }
+
+        Self {
+            message: error.as_inner().to_string(),
+            labels: error.spans()
+                .filter_map(|(span, message)| DiagnosticLabel::from_span(code, source_map, *span, message))
+                .collect(),
+            notes,
+        }
+    }
+
+    /// Render like rustc and codespan do, with a snippet of the source under each label
+    pub fn render(&self) -> String {
+        let gutter = self.labels.iter().map(|l| l.line.to_string().len()).max().unwrap_or(1);
+        let pad = " ".repeat(gutter);
+        let mut out = format!("error: {}\n", self.message);
+
+        for (i, label) in self.labels.iter().enumerate() {
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            out += &format!("{pad} {arrow} {}:{}:{}\n", label.file, label.line, label.column);
+
+            if let Some(source_line) = &label.source_line {
+                out += &format!("{pad} |\n");
+                out += &format!("{:>gutter$} | {source_line}\n", label.line);
+                let marker = if i == 0 { "^" } else { "-" };
+                out += &format!("{pad} | {}{} {}\n", " ".repeat(label.column - 1), marker.repeat(label.length), label.message);
+            } else if !label.message.is_empty() {
+                out += &format!("{pad} = {}\n", label.message);
+            }
+
+            let same_file = i > 0 && self.labels[i - 1].file == label.file;
+            if !label.import_chain.is_empty() && !same_file {
+                let chain: Vec<String> = label.import_chain.iter().map(|(file, line)| format!("{file}:{line}")).collect();
+                out += &format!("{pad} = imported through {} -> {}\n", chain.join(" -> "), label.file);
+            }
+        }
+
+        for note in &self.notes {
+            out += &format!("{pad} = note: {note}\n");
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
+
+impl From<&PreprocessError> for Diagnostic {
+    fn from(error: &PreprocessError) -> Self {
+        let at = |path: &str, line: usize| DiagnosticLabel {
+            file: path.to_string(),
+            line,
+            column: 1,
+            length: 1,
+            message: String::new(),
+            source_line: None,
+            import_chain: Vec::new(),
+        };
+
+        let labels = match error {
+            PreprocessError::BadImport { path, line, .. } | PreprocessError::BadDirective { path, line, .. } => vec![at(path, *line)],
+            PreprocessError::Load { path, .. } => vec![at(path, 1)],
+            PreprocessError::Cycle { .. } => Vec::new(),
+        };
+        Self {
+            message: error.to_string(),
+            labels,
+            notes: Vec::new(),
+        }
+    }
+}
+
+/// Parse and validate preprocessed WGSL with naga, mapping any error back to the files it was assembled from
+pub fn validate_wgsl(shader: &PreprocessedShader) -> Result<(naga::Module, naga::valid::ModuleInfo), Diagnostic> {
+    validate_wgsl_with_capabilities(shader, naga::valid::Capabilities::all())
+}
+
+/// Like `validate_wgsl`, rejecting features outside `capabilities` the way a device without them would
+pub fn validate_wgsl_with_capabilities(shader: &PreprocessedShader, capabilities: naga::valid::Capabilities) -> Result<(naga::Module, naga::valid::ModuleInfo), Diagnostic> {
+    let module = naga::front::wgsl::parse_str(&shader.code)
+        .map_err(|e| Diagnostic::from_parse_error(&e, &shader.code, &shader.source_map))?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), capabilities)
+        .validate(&module)
+        .map_err(|e| Diagnostic::from_validation_error(&e, &shader.code, &shader.source_map))?;
+    Ok((module, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::preprocess;
+
+    const MAIN: &str = "@import \"lib/helpers.wgsl\";\n\nfn main() -> f32 {\n    return helper();\n}\n";
+    const HELPERS: &str = "// é\nfn helper() -> f32 {\n    return missing_value;\n}\n";
+
+    fn shader() -> PreprocessedShader {
+        preprocess("main.wgsl", |path| match path {
+            "main.wgsl" => Ok(MAIN.to_string()),
+            "lib/helpers.wgsl" => Ok(HELPERS.to_string()),
+            _ => Err("not found".to_string()),
+        }).unwrap()
+    }
+
+    fn span_of(code: &str, text: &str) -> naga::Span {
+        let start = code.find(text).unwrap();
+        naga::Span::new(start as u32, (start + text.len()) as u32)
+    }
+
+    #[test]
+    fn spans_map_back_through_imports() {
+        let shader = shader();
+        let label = DiagnosticLabel::from_span(&shader.code, &shader.source_map, span_of(&shader.code, "missing_value"), "unknown").unwrap();
+        assert_eq!(label, DiagnosticLabel {
+            file: "lib/helpers.wgsl".into(),
+            line: 3,
+            column: 12,
+            length: 13,
+            message: "unknown".into(),
+            source_line: Some("    return missing_value;".into()),
+            import_chain: vec![("main.wgsl".into(), 1)],
+        });
+
+        let label = DiagnosticLabel::from_span(&shader.code, &shader.source_map, span_of(&shader.code, "return helper()"), "").unwrap();
+        assert_eq!((label.file.as_str(), label.line, label.column), ("main.wgsl", 4, 5));
+        assert!(label.import_chain.is_empty());
+    }
+
+    #[test]
+    fn columns_and_lengths_count_characters_on_one_line() {
+        let shader = shader();
+        // the span runs past the end of the line, so it's clipped there
+        let start = shader.code.find("é").unwrap();
+        let span = naga::Span::new(start as u32, shader.code.len() as u32);
+        let label = DiagnosticLabel::from_span(&shader.code, &shader.source_map, span, "").unwrap();
+        assert_eq!((label.line, label.column, label.length), (1, 4, 1));
+
+        let empty = naga::Span::new(start as u32 - 1, start as u32 - 1);
+        let label = DiagnosticLabel::from_span(&shader.code, &shader.source_map, empty, "").unwrap();
+        assert_eq!((label.column, label.length), (3, 1));
+
+        assert_eq!(DiagnosticLabel::from_span(&shader.code, &shader.source_map, naga::Span::UNDEFINED, ""), None);
+    }
+
+    #[test]
+    fn render_shows_the_snippet_and_import_chain() {
+        let diagnostic = validate_wgsl(&shader()).unwrap_err();
+        assert_eq!(diagnostic.labels[0].file, "lib/helpers.wgsl");
+        assert_eq!(diagnostic.render(), format!(
+            "error: {}\n  --> lib/helpers.wgsl:3:12\n  |\n3 |     return missing_value;\n  |            ^^^^^^^^^^^^^ {}\n  = imported through main.wgsl:1 -> lib/helpers.wgsl\n",
+            diagnostic.message, diagnostic.labels[0].message,
+        ));
+    }
+
+    #[test]
+    fn render_without_source_shows_the_message_and_notes() {
+        let diagnostic = Diagnostic {
+            message: "bad import".into(),
+            labels: vec![
+                DiagnosticLabel {
+                    file: "a.wgsl".into(),
+                    line: 12,
+                    column: 1,
+                    length: 1,
+                    message: "imported here".into(),
+                    source_line: None,
+                    import_chain: Vec::new(),
+                },
+            ],
+            notes: vec!["the file doesn't exist".into()],
+        };
+        assert_eq!(diagnostic.render(), "error: bad import\n   --> a.wgsl:12:1\n   = imported here\n   = note: the file doesn't exist\n");
+    }
+}
@@ -0,0 +1,6 @@
+//! Shader preprocessing and diagnostics for hb-gpu, kept to std and naga so hb-gpu-macros can use them at build time.
+//! Use them through the re-exports in `hb_gpu`
+
+pub mod preprocess;
+pub mod diagnostics;
+pub mod codegen;
@@ -0,0 +1,545 @@
+//! WGSL `@import "file.wgsl"` and `#ifdef` handling
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Something that stopped a shader from being preprocessed
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreprocessError {
+    /// A file couldn't be loaded, `imported_from` is `None` for the root shader
+    Load { path: String, imported_from: Option<String>, reason: String },
+    /// An `@import` line that isn't followed by a quoted path
+    BadImport { path: String, line: usize, text: String },
+    /// A malformed or unbalanced `#` directive
+    BadDirective { path: String, line: usize, message: String },
+    /// Files importing each other, the chain starts and ends with the same file
+    Cycle { chain: Vec<String> },
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Load { path, imported_from: Some(from), reason } => write!(f, "failed to load {path} imported from {from}: {reason}"),
+            PreprocessError::Load { path, imported_from: None, reason } => write!(f, "failed to load {path}: {reason}"),
+            PreprocessError::BadImport { path, line, text } => write!(f, "{path}:{line}: expected @import \"path\", got `{text}`"),
+            PreprocessError::BadDirective { path, line, message } => write!(f, "{path}:{line}: {message}"),
+            PreprocessError::Cycle { chain } => write!(f, "import cycle: {}", chain.join(" -> ")),
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+/// Which files a root shader pulled in, and who imported what
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DependencyGraph {
+    pub root: String,
+    /// Direct imports of every file in the graph, in the order they appear. Files without imports map to an empty list
+    pub imports: BTreeMap<String, Vec<String>>,
+}
+
+impl DependencyGraph {
+    /// Every file the root depends on, including the root itself
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.imports.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.imports.contains_key(&normalize_path(path))
+    }
+
+    /// Files that import `path` directly
+    pub fn importers_of(&self, path: &str) -> Vec<&str> {
+        let path = normalize_path(path);
+        self.imports.iter()
+            .filter(|(_, imports)| imports.contains(&path))
+            .map(|(file, _)| file.as_str())
+            .collect()
+    }
+}
+
+/// Names defined for a shader's `#ifdef` / `#if` blocks, with optional values
+///
+/// Also the second half of the key shader permutations are cached by, so it's ordered and hashable
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShaderDefs(BTreeMap<String, String>);
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defs with each name defined to an empty value
+    pub fn from_names(names: &[&str]) -> Self {
+        Self(names.iter().map(|name| (name.to_string(), String::new())).collect())
+    }
+
+    pub fn define(&mut self, name: &str, value: &str) -> &mut Self {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.0.remove(name);
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Evaluate an `#if` condition: `NAME`, `NAME == value` or `NAME != value`
+    fn evaluate(&self, condition: &str) -> Result<bool, String> {
+        for (op, equal) in [("==", true), ("!=", false)] {
+            if let Some((name, value)) = condition.split_once(op) {
+                let name = single_name(name.trim())?;
+                return Ok((self.get(name) == Some(value.trim())) == equal);
+            }
+        }
+        Ok(self.contains(single_name(condition)?))
+    }
+}
+
+/// Where each line of preprocessed code came from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<String>,
+    /// Index into `files` and 1-based line in that file, for every output line
+    lines: Vec<(u32, u32)>,
+    /// The `@import`s that first pulled each file in, outermost first, as (importing file, line of the import)
+    import_chains: BTreeMap<String, Vec<(String, usize)>>,
+}
+
+impl SourceMap {
+    /// A map for code that wasn't preprocessed, every line comes from `file` unchanged
+    pub fn identity(file: &str, code: &str) -> Self {
+        Self {
+            files: vec![file.to_string()],
+            lines: (1..=code.lines().count() as u32).map(|line| (0, line)).collect(),
+            import_chains: BTreeMap::new(),
+        }
+    }
+
+    /// The file and 1-based line that 1-based `line` of the preprocessed code came from
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        let &(file, line) = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[file as usize], line as usize))
+    }
+
+    /// How `file` was reached from the root, empty for the root itself
+    pub fn import_chain(&self, file: &str) -> &[(String, usize)] {
+        self.import_chains.get(file).map_or(&[], Vec::as_slice)
+    }
+
+    fn push(&mut self, file: &str, line: usize) {
+        let index = match self.files.iter().rposition(|f| f == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            },
+        };
+        self.lines.push((index as u32, line as u32));
+    }
+}
+
+/// A root shader with its imports inlined
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreprocessedShader {
+    pub code: String,
+    pub graph: DependencyGraph,
+    /// The defs it was preprocessed with
+    pub defs: ShaderDefs,
+    pub source_map: SourceMap,
+}
+
+/// Resolve `import` relative to the directory of `from`, paths starting with `/` are left alone
+pub fn resolve_import(from: &str, import: &str) -> String {
+    if import.starts_with('/') {
+        return normalize_path(import);
+    }
+    let from = from.replace('\\', "/");
+    match from.rfind('/') {
+        Some(i) => normalize_path(&format!("{}/{import}", &from[..i])),
+        None => normalize_path(import),
+    }
+}
+
+/// Fold `.` and `..` components and use `/` separators, so the same file always gets the same key
+pub fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            },
+            ".." if absolute => {},
+            _ => parts.push(part),
+        }
+    }
+
+    let joined = parts.join("/");
+    if absolute { format!("/{joined}") } else { joined }
+}
+
+/// Parse an `@import "path";` line, `None` if the line isn't an import at all
+fn parse_import(line: &str) -> Option<Result<&str, ()>> {
+    let rest = line.trim().strip_prefix("@import")?;
+    if rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    let rest = rest.trim();
+    let rest = rest.strip_suffix(';').unwrap_or(rest).trim_end();
+    Some(rest.strip_prefix('"').and_then(|s| s.strip_suffix('"')).filter(|s| !s.is_empty() && !s.contains('"')).ok_or(()))
+}
+
+/// The paths a file imports, resolved against `path`, in the order they appear
+pub fn find_imports(path: &str, source: &str) -> Result<Vec<String>, PreprocessError> {
+    source.lines().enumerate()
+        .filter_map(|(i, line)| Some((i, line, parse_import(line)?)))
+        .map(|(i, line, import)| match import {
+            Ok(import) => Ok(resolve_import(path, import)),
+            Err(()) => Err(PreprocessError::BadImport { path: path.to_string(), line: i + 1, text: line.trim().to_string() }),
+        })
+        .collect()
+}
+
+/// Inline every `@import` reachable from `root`, loading files with `load`
+///
+/// Imports are resolved relative to the importing file. Each file is inlined once, where it is first imported, and
+/// later imports of it are dropped. `load` gets normalized paths and returns the file's contents or why it couldn't
+/// be read
+pub fn preprocess(root: &str, load: impl FnMut(&str) -> Result<String, String>) -> Result<PreprocessedShader, PreprocessError> {
+    preprocess_with_defs(root, &ShaderDefs::new(), load)
+}
+
+/// Like `preprocess`, with `defs` deciding which conditional blocks are kept
+///
+/// Supported directives, each on its own line:
+/// - `#ifdef NAME`, `#ifndef NAME`, `#if NAME`, `#if NAME == value`, `#if NAME != value`, `#elif ...`, `#else`, `#endif`
+/// - `#define NAME [value]` and `#undef NAME`, which apply to the rest of the file and everything imported after them
+///
+/// Define values are only compared by `#if`, they aren't substituted into the code. Use `override` constants with
+/// `PipelineConstants` to feed numbers like workgroup sizes into a shader. Imports inside skipped blocks aren't loaded
+pub fn preprocess_with_defs(root: &str, defs: &ShaderDefs, mut load: impl FnMut(&str) -> Result<String, String>) -> Result<PreprocessedShader, PreprocessError> {
+    let root = normalize_path(root);
+    let mut state = State {
+        load: &mut load,
+        defs: defs.clone(),
+        graph: DependencyGraph { root: root.clone(), imports: BTreeMap::new() },
+        stack: Vec::new(),
+        code: String::new(),
+        source_map: SourceMap::default(),
+    };
+    state.include(&root, None)?;
+
+    Ok(PreprocessedShader {
+        code: state.code,
+        graph: state.graph,
+        defs: defs.clone(),
+        source_map: state.source_map,
+    })
+}
+
+/// One open `#if` block
+struct Conditional {
+    /// Line of the `#if`, for unterminated block errors
+    line: usize,
+    /// Whether the enclosing block is kept
+    parent_active: bool,
+    /// Whether a branch of this block has been kept already
+    taken: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+struct State<'a> {
+    load: &'a mut dyn FnMut(&str) -> Result<String, String>,
+    defs: ShaderDefs,
+    graph: DependencyGraph,
+    /// Files currently being inlined, to catch cycles
+    stack: Vec<String>,
+    code: String,
+    source_map: SourceMap,
+}
+
+impl State<'_> {
+    /// Inline `path`, `imported_from` is the file and line of the `@import` that pulled it in
+    fn include(&mut self, path: &str, imported_from: Option<(&str, usize)>) -> Result<(), PreprocessError> {
+        let source = (self.load)(path).map_err(|reason| PreprocessError::Load {
+            path: path.to_string(),
+            imported_from: imported_from.map(|(file, _)| file.to_string()),
+            reason,
+        })?;
+
+        if let Some((file, line)) = imported_from {
+            let mut chain = self.source_map.import_chain(file).to_vec();
+            chain.push((file.to_string(), line));
+            self.source_map.import_chains.insert(path.to_string(), chain);
+        }
+
+        self.graph.imports.insert(path.to_string(), Vec::new());
+        self.stack.push(path.to_string());
+
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let error = |message: String| PreprocessError::BadDirective { path: path.to_string(), line: i + 1, message };
+
+            if let Some(directive) = line.trim().strip_prefix('#') {
+                let (name, args) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+                let args = args.trim();
+                match name {
+                    "ifdef" | "ifndef" | "if" => {
+                        let condition = match name {
+                            "ifdef" => self.defs.contains(single_name(args).map_err(error)?),
+                            "ifndef" => !self.defs.contains(single_name(args).map_err(error)?),
+                            _ => self.defs.evaluate(args).map_err(error)?,
+                        };
+                        conditionals.push(Conditional {
+                            line: i + 1,
+                            parent_active: active,
+                            taken: active && condition,
+                            active: active && condition,
+                            seen_else: false,
+                        });
+                    },
+                    "elif" => {
+                        let condition = self.defs.evaluate(args).map_err(error)?;
+                        let Some(block) = conditionals.last_mut().filter(|c| !c.seen_else) else {
+                            return Err(error("#elif without a matching #if".to_string()));
+                        };
+                        block.active = block.parent_active && !block.taken && condition;
+                        block.taken |= block.active;
+                    },
+                    "else" => {
+                        let Some(block) = conditionals.last_mut().filter(|c| !c.seen_else) else {
+                            return Err(error("#else without a matching #if".to_string()));
+                        };
+                        block.active = block.parent_active && !block.taken;
+                        block.seen_else = true;
+                    },
+                    "endif" => {
+                        if conditionals.pop().is_none() {
+                            return Err(error("#endif without a matching #if".to_string()));
+                        }
+                    },
+                    "define" if active => {
+                        let (name, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                        self.defs.define(single_name(name).map_err(error)?, value.trim());
+                    },
+                    "undef" if active => {
+                        self.defs.undefine(single_name(args).map_err(error)?);
+                    },
+                    "define" | "undef" => {},
+                    _ => return Err(error(format!("unknown directive #{name}"))),
+                }
+                continue;
+            }
+
+            if !active {
+                continue;
+            }
+
+            let import = match parse_import(line) {
+                None => {
+                    self.code.push_str(line);
+                    self.code.push('\n');
+                    self.source_map.push(path, i + 1);
+                    continue;
+                },
+                Some(Ok(import)) => resolve_import(path, import),
+                Some(Err(())) => return Err(PreprocessError::BadImport { path: path.to_string(), line: i + 1, text: line.trim().to_string() }),
+            };
+
+            if let Some(start) = self.stack.iter().position(|p| *p == import) {
+                let mut chain = self.stack[start..].to_vec();
+                chain.push(import);
+                return Err(PreprocessError::Cycle { chain });
+            }
+            self.graph.imports.get_mut(path).expect("file was just added").push(import.clone());
+            if !self.graph.imports.contains_key(&import) {
+                self.include(&import, Some((path, i + 1)))?;
+            }
+        }
+
+        if let Some(block) = conditionals.last() {
+            return Err(PreprocessError::BadDirective { path: path.to_string(), line: block.line, message: "#if without a matching #endif".to_string() });
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+fn single_name(args: &str) -> Result<&str, String> {
+    let valid = !args.is_empty() && args.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid { Ok(args) } else { Err(format!("expected a define name, got `{args}`")) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files<'a>(files: &'a [(&'a str, &'a str)]) -> impl FnMut(&str) -> Result<String, String> + 'a {
+        move |path| files.iter()
+            .find(|(name, _)| *name == path)
+            .map(|(_, source)| source.to_string())
+            .ok_or_else(|| "not found".to_string())
+    }
+
+    fn lines(code: &str) -> Vec<&str> {
+        code.lines().collect()
+    }
+
+    #[test]
+    fn resolves_imports_relative_to_the_importer() {
+        assert_eq!(resolve_import("shaders/main.wgsl", "lib/math.wgsl"), "shaders/lib/math.wgsl");
+        assert_eq!(resolve_import("shaders/lib/math.wgsl", "../common.wgsl"), "shaders/common.wgsl");
+        assert_eq!(resolve_import("shaders/main.wgsl", "/abs/x.wgsl"), "/abs/x.wgsl");
+        assert_eq!(normalize_path("a\\b/./c/../d.wgsl"), "a/b/d.wgsl");
+        assert_eq!(normalize_path("../a/../../b.wgsl"), "../../b.wgsl");
+    }
+
+    #[test]
+    fn inlines_imports_in_place() {
+        let shader = preprocess("shaders/main.wgsl", files(&[
+            ("shaders/main.wgsl", "@import \"lib/math.wgsl\";\nfn main() {}"),
+            ("shaders/lib/math.wgsl", "@import \"../common.wgsl\"\nfn square() {}"),
+            ("shaders/common.wgsl", "const PI = 3.14;"),
+        ])).unwrap();
+
+        assert_eq!(lines(&shader.code), ["const PI = 3.14;", "fn square() {}", "fn main() {}"]);
+        assert_eq!(shader.graph.imports["shaders/main.wgsl"], ["shaders/lib/math.wgsl"]);
+        assert_eq!(shader.graph.importers_of("shaders/common.wgsl"), ["shaders/lib/math.wgsl"]);
+        assert!(shader.graph.contains("shaders/./common.wgsl"));
+    }
+
+    #[test]
+    fn inlines_each_file_once() {
+        let shader = preprocess("main.wgsl", files(&[
+            ("main.wgsl", "@import \"a.wgsl\"\n@import \"b.wgsl\"\n@import \"a.wgsl\""),
+            ("a.wgsl", "@import \"common.wgsl\"\nfn a() {}"),
+            ("b.wgsl", "@import \"common.wgsl\"\nfn b() {}"),
+            ("common.wgsl", "fn common() {}"),
+        ])).unwrap();
+
+        assert_eq!(lines(&shader.code), ["fn common() {}", "fn a() {}", "fn b() {}"]);
+        // the graph still records every import, duplicates included
+        assert_eq!(shader.graph.imports["main.wgsl"], ["a.wgsl", "b.wgsl", "a.wgsl"]);
+        assert_eq!(shader.graph.importers_of("common.wgsl"), ["a.wgsl", "b.wgsl"]);
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let error = preprocess("a.wgsl", files(&[
+            ("a.wgsl", "@import \"b.wgsl\""),
+            ("b.wgsl", "@import \"c.wgsl\""),
+            ("c.wgsl", "@import \"b.wgsl\""),
+        ])).unwrap_err();
+
+        assert_eq!(error, PreprocessError::Cycle { chain: vec!["b.wgsl".into(), "c.wgsl".into(), "b.wgsl".into()] });
+    }
+
+    #[test]
+    fn reports_missing_files_and_bad_imports() {
+        let error = preprocess("main.wgsl", files(&[("main.wgsl", "@import \"missing.wgsl\"")])).unwrap_err();
+        assert_eq!(error, PreprocessError::Load {
+            path: "missing.wgsl".into(),
+            imported_from: Some("main.wgsl".into()),
+            reason: "not found".into(),
+        });
+
+        let error = preprocess("main.wgsl", files(&[("main.wgsl", "fn f() {}\n@import missing.wgsl")])).unwrap_err();
+        assert!(matches!(error, PreprocessError::BadImport { line: 2, .. }));
+
+        // identifiers that merely start with `@import` aren't imports
+        assert_eq!(find_imports("main.wgsl", "@imported fn f() {}"), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn keeps_the_first_matching_branch() {
+        let source = "#if MODE == fast\nfast\n#elif MODE == slow\nslow\n#elif DEBUG\ndebug\n#else\nfallback\n#endif";
+        let run = |defs: &ShaderDefs| preprocess_with_defs("main.wgsl", defs, files(&[("main.wgsl", source)])).unwrap().code;
+
+        assert_eq!(run(ShaderDefs::new().define("MODE", "fast")), "fast\n");
+        assert_eq!(run(ShaderDefs::new().define("MODE", "slow").define("DEBUG", "")), "slow\n");
+        assert_eq!(run(&ShaderDefs::from_names(&["DEBUG"])), "debug\n");
+        assert_eq!(run(&ShaderDefs::new()), "fallback\n");
+    }
+
+    #[test]
+    fn nested_blocks_inside_skipped_blocks_stay_skipped() {
+        let source = "#ifdef OUTER\n#ifndef INNER\na\n#else\nb\n#endif\n#else\n#if INNER\nc\n#endif\nd\n#endif";
+        let run = |names: &[&str]| preprocess_with_defs("main.wgsl", &ShaderDefs::from_names(names), files(&[("main.wgsl", source)])).unwrap().code;
+
+        assert_eq!(run(&["OUTER"]), "a\n");
+        assert_eq!(run(&["OUTER", "INNER"]), "b\n");
+        assert_eq!(run(&["INNER"]), "c\nd\n");
+        assert_eq!(run(&[]), "d\n");
+    }
+
+    #[test]
+    fn defines_carry_into_later_imports() {
+        let shader = preprocess_with_defs("main.wgsl", &ShaderDefs::new(), files(&[
+            ("main.wgsl", "#define SHADOWS\n@import \"lib.wgsl\"\n#undef SHADOWS\n#ifdef SHADOWS\nunreachable\n#endif"),
+            ("lib.wgsl", "#ifdef SHADOWS\nshadows\n#endif"),
+        ])).unwrap();
+
+        assert_eq!(shader.code, "shadows\n");
+        // the defs passed in are recorded, not the ones defined along the way
+        assert_eq!(shader.defs, ShaderDefs::new());
+    }
+
+    #[test]
+    fn skipped_imports_are_not_loaded() {
+        let shader = preprocess("main.wgsl", files(&[("main.wgsl", "#ifdef MISSING\n@import \"missing.wgsl\"\n#endif\nfn f() {}")])).unwrap();
+        assert_eq!(shader.code, "fn f() {}\n");
+        assert!(!shader.graph.contains("missing.wgsl"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_directives() {
+        let run = |source: &str| preprocess("main.wgsl", files(&[("main.wgsl", source)])).unwrap_err();
+
+        assert!(matches!(run("#ifdef A\nfn f() {}"), PreprocessError::BadDirective { line: 1, .. }));
+        assert!(matches!(run("fn f() {}\n#endif"), PreprocessError::BadDirective { line: 2, .. }));
+        assert!(matches!(run("#ifdef A\n#else\n#elif B\n#endif"), PreprocessError::BadDirective { line: 3, .. }));
+        assert!(matches!(run("#ifdef A B\n#endif"), PreprocessError::BadDirective { line: 1, .. }));
+        assert!(matches!(run("#pragma once"), PreprocessError::BadDirective { line: 1, .. }));
+    }
+
+    #[test]
+    fn maps_output_lines_back_to_their_files() {
+        let shader = preprocess_with_defs("main.wgsl", &ShaderDefs::new(), files(&[
+            ("main.wgsl", "// main\n@import \"lib/a.wgsl\"\n#ifdef X\nskipped\n#endif\nfn main() {}"),
+            ("lib/a.wgsl", "\n@import \"b.wgsl\"\nfn a() {}"),
+            ("lib/b.wgsl", "fn b() {}"),
+        ])).unwrap();
+
+        assert_eq!(lines(&shader.code), ["// main", "", "fn b() {}", "fn a() {}", "fn main() {}"]);
+        let map = &shader.source_map;
+        assert_eq!(map.locate(1), Some(("main.wgsl", 1)));
+        assert_eq!(map.locate(2), Some(("lib/a.wgsl", 1)));
+        assert_eq!(map.locate(3), Some(("lib/b.wgsl", 1)));
+        assert_eq!(map.locate(4), Some(("lib/a.wgsl", 3)));
+        assert_eq!(map.locate(5), Some(("main.wgsl", 6)));
+        assert_eq!(map.locate(0), None);
+        assert_eq!(map.locate(6), None);
+
+        assert_eq!(map.import_chain("main.wgsl"), []);
+        assert_eq!(map.import_chain("lib/b.wgsl"), [("main.wgsl".to_string(), 2), ("lib/a.wgsl".to_string(), 2)]);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use glam::{UVec2, Vec2};
+use image::RgbaImage;
+
+use crate::{gpu::Gpu, texture::{Texture, TextureError}};
+
+/// Where an image ended up in a `TextureAtlas`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasRect {
+    /// Index into `TextureAtlas::pages`
+    pub page: usize,
+    /// Top left corner of the image in pixels, excluding padding
+    pub position: UVec2,
+    pub size: UVec2,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+/// Skyline bottom-left packer, tracks the top edge of the packed area as a list of horizontal segments
+struct Skyline {
+    size: UVec2,
+    // (x, y, width) of each segment, sorted by x
+    segments: Vec<(u32, u32, u32)>,
+}
+
+impl Skyline {
+    fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            segments: vec![(0, 0, size.x)],
+        }
+    }
+
+    /// Lowest y a `size` rect can sit at when its left edge is at segment `i`
+    fn fit(&self, i: usize, size: UVec2) -> Option<u32> {
+        let x = self.segments[i].0;
+        if x + size.x > self.size.x {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut remaining = size.x as i64;
+        for &(_, segment_y, segment_width) in &self.segments[i..] {
+            if remaining <= 0 {
+                break;
+            }
+            y = y.max(segment_y);
+            if y + size.y > self.size.y {
+                return None;
+            }
+            remaining -= segment_width as i64;
+        }
+        Some(y)
+    }
+
+    fn insert(&mut self, size: UVec2) -> Option<UVec2> {
+        let (i, y) = (0..self.segments.len())
+            .filter_map(|i| Some((i, self.fit(i, size)?)))
+            .min_by_key(|&(i, y)| (y + size.y, self.segments[i].0))?;
+
+        let x = self.segments[i].0;
+        self.segments.insert(i, (x, y + size.y, size.x));
+
+        // trim or remove the segments now covered by the new one
+        let right = x + size.x;
+        let j = i + 1;
+        while j < self.segments.len() {
+            let (sx, sy, sw) = self.segments[j];
+            if sx >= right {
+                break;
+            }
+            if sx + sw <= right {
+                self.segments.remove(j);
+            } else {
+                self.segments[j] = (right, sy, sx + sw - right);
+                break;
+            }
+        }
+
+        // merge neighbours at the same height
+        let mut k = 0;
+        while k + 1 < self.segments.len() {
+            if self.segments[k].1 == self.segments[k + 1].1 {
+                self.segments[k].2 += self.segments[k + 1].2;
+                self.segments.remove(k + 1);
+            } else {
+                k += 1;
+            }
+        }
+
+        Some(UVec2::new(x, y))
+    }
+}
+
+pub struct AtlasPage {
+    pub texture: Texture,
+    packer: Skyline,
+}
+
+/// Packs many images into `Rgba8UnormSrgb` pages, adding pages as they fill up
+///
+/// Each image gets `padding` pixels of space on every side. With `extrude` set, the padding is filled with
+/// copies of the image's edge pixels so linear filtering and mipmapping don't bleed neighbouring images in
+pub struct TextureAtlas<K: Hash + Eq = String> {
+    pub pages: Vec<AtlasPage>,
+    pub page_size: UVec2,
+    pub padding: u32,
+    pub extrude: bool,
+    rects: HashMap<K, AtlasRect>,
+}
+
+impl<K: Hash + Eq> TextureAtlas<K> {
+    /// Create an empty atlas, the first page is allocated when the first image is added
+    pub fn new(page_size: UVec2, padding: u32) -> Self {
+        Self {
+            pages: Vec::new(),
+            page_size,
+            padding,
+            extrude: padding > 0,
+            rects: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&AtlasRect> {
+        self.rects.get(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.rects.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &AtlasRect)> {
+        self.rects.iter()
+    }
+
+    /// Load an image file and add it to the atlas
+    pub fn insert_file(&mut self, gpu: &Gpu, key: K, path: &str) -> Result<AtlasRect, TextureError> {
+        let image = image::ImageReader::open(path)?.with_guessed_format()?.decode()?;
+        self.insert_image(gpu, key, &image.to_rgba8())
+    }
+
+    /// Decode an encoded image in memory and add it to the atlas
+    pub fn insert_bytes(&mut self, gpu: &Gpu, key: K, bytes: &[u8]) -> Result<AtlasRect, TextureError> {
+        let image = image::load_from_memory(bytes)?;
+        self.insert_image(gpu, key, &image.to_rgba8())
+    }
+
+    /// Add an image, replacing the lookup for `key` if it was already present (the old space is not reclaimed)
+    pub fn insert_image(&mut self, gpu: &Gpu, key: K, image: &RgbaImage) -> Result<AtlasRect, TextureError> {
+        let size = UVec2::new(image.width(), image.height());
+        if size.x == 0 || size.y == 0 {
+            return Err(TextureError::Other("can't add an empty image to an atlas".into()));
+        }
+
+        let padded_size = size + UVec2::splat(self.padding * 2);
+        if padded_size.x > self.page_size.x || padded_size.y > self.page_size.y {
+            return Err(TextureError::Other(format!(
+                "{}x{} image with {} pixels of padding does not fit in a {}x{} atlas page",
+                size.x, size.y, self.padding, self.page_size.x, self.page_size.y
+            )));
+        }
+
+        let placed = self.pages.iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| Some((i, page.packer.insert(padded_size)?)));
+
+        let (page, corner) = match placed {
+            Some(placed) => placed,
+            None => {
+                let mut packer = Skyline::new(self.page_size);
+                let corner = packer.insert(padded_size).expect("padded image fits in an empty page");
+                self.pages.push(AtlasPage {
+                    texture: gpu.new_texture(self.page_size, wgpu::TextureFormat::Rgba8UnormSrgb, false),
+                    packer,
+                });
+                (self.pages.len() - 1, corner)
+            },
+        };
+
+        let padded = self.pad(image);
+        gpu.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.pages[page].texture.raw,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: corner.x, y: corner.y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            padded.as_raw(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_size.x * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: padded_size.x,
+                height: padded_size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let position = corner + UVec2::splat(self.padding);
+        let page_size = self.page_size.as_vec2();
+        let rect = AtlasRect {
+            page,
+            position,
+            size,
+            uv_min: position.as_vec2() / page_size,
+            uv_max: (position + size).as_vec2() / page_size,
+        };
+        self.rects.insert(key, rect);
+        Ok(rect)
+    }
+
+    /// Surround `image` with `padding` pixels, either transparent or extruded from the edges
+    fn pad(&self, image: &RgbaImage) -> RgbaImage {
+        let p = self.padding;
+        if p == 0 {
+            return image.clone();
+        }
+
+        let (w, h) = image.dimensions();
+        RgbaImage::from_fn(w + p * 2, h + p * 2, |x, y| {
+            let inside = (p..p + w).contains(&x) && (p..p + h).contains(&y);
+            if inside || self.extrude {
+                let sx = x.saturating_sub(p).min(w - 1);
+                let sy = y.saturating_sub(p).min(h - 1);
+                *image.get_pixel(sx, sy)
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: (UVec2, UVec2), b: (UVec2, UVec2)) -> bool {
+        a.0.x < b.0.x + b.1.x && b.0.x < a.0.x + a.1.x && a.0.y < b.0.y + b.1.y && b.0.y < a.0.y + a.1.y
+    }
+
+    #[test]
+    fn skyline_packs_bottom_left() {
+        let mut packer = Skyline::new(UVec2::new(10, 10));
+        assert_eq!(packer.insert(UVec2::new(4, 3)), Some(UVec2::new(0, 0)));
+        assert_eq!(packer.insert(UVec2::new(4, 2)), Some(UVec2::new(4, 0)));
+        // the lowest top edge wins, so this goes beside the others instead of on top of them
+        assert_eq!(packer.insert(UVec2::new(2, 5)), Some(UVec2::new(8, 0)));
+        assert_eq!(packer.insert(UVec2::new(4, 1)), Some(UVec2::new(4, 2)));
+        assert_eq!(packer.segments, [(0, 3, 8), (8, 5, 2)]);
+    }
+
+    #[test]
+    fn skyline_fills_the_page_then_refuses() {
+        let mut packer = Skyline::new(UVec2::new(8, 8));
+        for _ in 0..4 {
+            assert!(packer.insert(UVec2::new(4, 4)).is_some());
+        }
+        assert_eq!(packer.segments, [(0, 8, 8)]);
+        assert_eq!(packer.insert(UVec2::new(1, 1)), None);
+
+        let mut packer = Skyline::new(UVec2::new(8, 8));
+        assert_eq!(packer.insert(UVec2::new(9, 1)), None);
+        assert_eq!(packer.insert(UVec2::new(1, 9)), None);
+        assert_eq!(packer.insert(UVec2::new(8, 8)), Some(UVec2::ZERO));
+    }
+
+    #[test]
+    fn skyline_rects_never_overlap() {
+        let page = UVec2::new(64, 64);
+        let mut packer = Skyline::new(page);
+        let mut placed: Vec<(UVec2, UVec2)> = Vec::new();
+        // a fixed spread of sizes, enough to run out of room
+        for i in 0..200u32 {
+            let size = UVec2::new(1 + (i * 7) % 13, 1 + (i * 11) % 9);
+            let Some(position) = packer.insert(size) else { continue };
+            assert!(position.x + size.x <= page.x && position.y + size.y <= page.y);
+            assert!(placed.iter().all(|&rect| !overlaps(rect, (position, size))), "{size} at {position} overlaps");
+            placed.push((position, size));
+        }
+        assert!(placed.len() > 40);
+    }
+
+    #[test]
+    fn padding_extrudes_the_edges() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+        let atlas: TextureAtlas = TextureAtlas::new(UVec2::splat(16), 2);
+        let padded = atlas.pad(&image);
+
+        assert_eq!(padded.dimensions(), (6, 6));
+        assert_eq!(padded.get_pixel(2, 2), image.get_pixel(0, 0));
+        assert_eq!(padded.get_pixel(3, 3), image.get_pixel(1, 1));
+        assert_eq!(padded.get_pixel(0, 0), image.get_pixel(0, 0));
+        assert_eq!(padded.get_pixel(5, 0), image.get_pixel(1, 0));
+        assert_eq!(padded.get_pixel(0, 5), image.get_pixel(0, 1));
+    }
+
+    #[test]
+    fn padding_without_extrude_is_transparent() {
+        let image = RgbaImage::from_pixel(2, 2, image::Rgba([255; 4]));
+        let mut atlas: TextureAtlas = TextureAtlas::new(UVec2::splat(16), 1);
+        atlas.extrude = false;
+        let padded = atlas.pad(&image);
+
+        assert_eq!(padded.dimensions(), (4, 4));
+        assert_eq!(*padded.get_pixel(0, 0), image::Rgba([0; 4]));
+        assert_eq!(*padded.get_pixel(3, 2), image::Rgba([0; 4]));
+        assert_eq!(*padded.get_pixel(1, 2), image::Rgba([255; 4]));
+
+        let atlas: TextureAtlas = TextureAtlas::new(UVec2::splat(16), 0);
+        assert_eq!(atlas.pad(&image), image);
+    }
+}
@@ -0,0 +1,575 @@
+//! Offline shader checker, runs the import preprocessor and naga validation without a GPU
+//!
+//! Prints what the shader declares and can write SPIR-V, MSL, HLSL and GLSL translations for inspection, or Rust
+//! mirrors of its buffer structs.
+//! Exits with 1 if any file fails, so it can run in CI
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use hb_gpu::codegen::generate_rust;
+use hb_gpu::diagnostics::{validate_wgsl_with_capabilities, Diagnostic};
+use hb_gpu::preprocess::{preprocess_with_defs, PreprocessedShader, ShaderDefs};
+use hb_gpu::reflect::{describe_binding, PipelineConstants, ShaderLayout};
+use hb_gpu::translate::{translate, ShaderLanguage};
+use naga::common::wgsl::TypeContext;
+
+const USAGE: &str = "\
+Usage: hb-gpu-shaderc [options] <file>...
+
+Files can be WGSL, GLSL (.vert, .frag, .comp) or SPIR-V (.spv), the last two are checked after translating to WGSL
+
+Options:
+  -D NAME[=VALUE]       define NAME for #ifdef / #if blocks or GLSL #defines, can be repeated
+  -C KEY=VALUE          set an override constant by its @id, or its name if it has none
+  --capabilities LIST   comma separated naga capabilities like FLOAT64,CUBE_ARRAY_TEXTURES,
+                        or `all` / `none` (default: naga's defaults)
+  --limits NAME         check workgroup sizes and binding counts against `default`, `downlevel`
+                        or `webgl2` limits (default: default)
+  --emit LIST           comma separated spv, msl, hlsl, glsl, and rust for the structs buffers use
+  -o DIR                where --emit writes files (default: next to each input). Inputs that share a
+                        file stem keep their extension, like foo.vert.spv and foo.frag.spv
+  -q                    only print errors
+";
+
+struct Args {
+    files: Vec<String>,
+    defs: ShaderDefs,
+    constants: Vec<(String, f64)>,
+    capabilities: naga::valid::Capabilities,
+    limits: (String, wgpu::Limits),
+    emit: Vec<String>,
+    out_dir: Option<PathBuf>,
+    quiet: bool,
+}
+
+fn parse_args(command_line: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = Args {
+        files: Vec::new(),
+        defs: ShaderDefs::new(),
+        constants: Vec::new(),
+        capabilities: naga::valid::Capabilities::default(),
+        limits: ("default".to_string(), wgpu::Limits::default()),
+        emit: Vec::new(),
+        out_dir: None,
+        quiet: false,
+    };
+
+    let mut iter = command_line.into_iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => return Err(String::new()),
+            "-q" => args.quiet = true,
+            "-D" => {
+                let define = value("-D")?;
+                let (name, value) = define.split_once('=').unwrap_or((&define, ""));
+                args.defs.define(name, value);
+            },
+            "-C" => {
+                let constant = value("-C")?;
+                let (key, value) = constant.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got `{constant}`"))?;
+                let value = match value {
+                    "true" => 1.0,
+                    "false" => 0.0,
+                    _ => value.parse().map_err(|_| format!("`{value}` isn't a number"))?,
+                };
+                args.constants.push((key.to_string(), value));
+            },
+            "--capabilities" => args.capabilities = parse_capabilities(&value("--capabilities")?)?,
+            "--limits" => {
+                let name = value("--limits")?;
+                let limits = match name.as_str() {
+                    "default" => wgpu::Limits::default(),
+                    "downlevel" => wgpu::Limits::downlevel_defaults(),
+                    "webgl2" => wgpu::Limits::downlevel_webgl2_defaults(),
+                    _ => return Err(format!("unknown limits `{name}`")),
+                };
+                args.limits = (name, limits);
+            },
+            "--emit" => {
+                for format in value("--emit")?.split(',') {
+                    if !["spv", "msl", "hlsl", "glsl", "rust"].contains(&format) {
+                        return Err(format!("unknown output format `{format}`"));
+                    }
+                    args.emit.push(format.to_string());
+                }
+            },
+            "-o" => args.out_dir = Some(PathBuf::from(value("-o")?)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+            _ => args.files.push(arg),
+        }
+    }
+
+    if args.files.is_empty() {
+        return Err("no input files".to_string());
+    }
+    Ok(args)
+}
+
+fn parse_capabilities(list: &str) -> Result<naga::valid::Capabilities, String> {
+    match list {
+        "all" => return Ok(naga::valid::Capabilities::all()),
+        "none" => return Ok(naga::valid::Capabilities::empty()),
+        _ => {},
+    }
+    list.split(',').try_fold(naga::valid::Capabilities::empty(), |caps, name| {
+        naga::valid::Capabilities::from_name(name.trim())
+            .map(|cap| caps | cap)
+            .ok_or_else(|| format!("unknown capability `{name}`"))
+    })
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("error: {e}\n");
+            }
+            eprint!("{USAGE}");
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let mut failed = false;
+    for file in &args.files {
+        if let Err(diagnostic) = check_file(file, &args) {
+            eprint!("{}", diagnostic.render());
+            failed = true;
+        }
+    }
+
+    if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+}
+
+fn error(message: String) -> Diagnostic {
+    Diagnostic { message, labels: Vec::new(), notes: Vec::new() }
+}
+
+fn check_file(file: &str, args: &Args) -> Result<(), Diagnostic> {
+    let shader = match ShaderLanguage::from_path(file) {
+        ShaderLanguage::Wgsl => preprocess_with_defs(file, &args.defs, |path| std::fs::read_to_string(path).map_err(|e| e.to_string()))
+            .map_err(|e| Diagnostic::from(&e))?,
+        language => {
+            let bytes = std::fs::read(file).map_err(|e| error(format!("failed to load {file}: {e}")))?;
+            translate(file, language, &bytes, &args.defs)?
+        },
+    };
+    let (module, info) = validate_wgsl_with_capabilities(&shader, args.capabilities)?;
+
+    let layout = ShaderLayout::from_module(&module, &info).map_err(|e| error(format!("{file}: {e}")))?;
+    let mut constants = PipelineConstants::new();
+    for (key, value) in &args.constants {
+        constants.set(key, *value);
+    }
+    layout.check_constants(&constants).map_err(|e| error(format!("{file}: {e}")))?;
+    if !args.quiet {
+        print_reflection(&shader, &module, &layout);
+    }
+
+    // the backends need concrete values for every override
+    let constants = constants.pairs().into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+    let (module, info) = naga::back::pipeline_constants::process_overrides(&module, &info, None, &constants)
+        .map_err(|e| error(format!("{file}: {e}")))?;
+
+    let problems = check_limits(&module, &layout, &args.limits.1);
+    if !problems.is_empty() {
+        return Err(Diagnostic {
+            message: format!("{file} exceeds the {} limits", args.limits.0),
+            labels: Vec::new(),
+            notes: problems,
+        });
+    }
+
+    for format in &args.emit {
+        let written = emit(format, file, &output_stem(file, &args.files), &module, &info, args.out_dir.as_deref())
+            .map_err(|e| error(format!("{file}: writing {format} failed: {e}")))?;
+        if !args.quiet {
+            for path in written {
+                println!("  wrote {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn stage_name(stage: naga::ShaderStage) -> &'static str {
+    match stage {
+        naga::ShaderStage::Vertex => "vertex",
+        naga::ShaderStage::Fragment => "fragment",
+        naga::ShaderStage::Compute => "compute",
+        naga::ShaderStage::Task => "task",
+        naga::ShaderStage::Mesh => "mesh",
+    }
+}
+
+fn print_reflection(shader: &PreprocessedShader, module: &naga::Module, layout: &ShaderLayout) {
+    println!("{}", shader.graph.root);
+    let imports: Vec<&str> = shader.graph.files().filter(|f| *f != shader.graph.root).collect();
+    if !imports.is_empty() {
+        println!("  imports: {}", imports.join(", "));
+    }
+
+    println!("  entry points:");
+    for ep in &module.entry_points {
+        if ep.stage == naga::ShaderStage::Compute {
+            let [x, y, z] = ep.workgroup_size;
+            println!("    @compute @workgroup_size({x}, {y}, {z}) {}", ep.name);
+        } else {
+            println!("    @{} {}", stage_name(ep.stage), ep.name);
+        }
+    }
+
+    if !layout.groups.is_empty() {
+        println!("  bindings:");
+    }
+    for (group, entries) in &layout.groups {
+        for entry in entries.entries() {
+            let name = module.global_variables.iter()
+                .find(|(_, var)| var.binding.as_ref().is_some_and(|b| b.group == *group && b.binding == entry.binding))
+                .and_then(|(_, var)| var.name.as_deref())
+                .unwrap_or("?");
+            println!("    @group({group}) @binding({}) {name}: {} ({:?})", entry.binding, describe_binding(&entry.ty, entry.count), entry.visibility);
+        }
+    }
+
+    if !layout.overrides.is_empty() {
+        println!("  overrides:");
+    }
+    for (key, constant) in &layout.overrides {
+        let default = if constant.has_default { "" } else { ", required" };
+        println!("    {key}: {}{default}", constant.ty);
+    }
+
+    let mut layouter = naga::proc::Layouter::default();
+    if layouter.update(module.to_ctx()).is_err() {
+        return;
+    }
+    let ctx = module.to_ctx();
+    let structs: Vec<_> = module.types.iter()
+        .filter_map(|(handle, ty)| match &ty.inner {
+            naga::TypeInner::Struct { members, span } => Some((handle, ty, members, span)),
+            _ => None,
+        })
+        .collect();
+    if !structs.is_empty() {
+        println!("  structs:");
+    }
+    for (handle, ty, members, span) in structs {
+        println!("    {} (size {span}, align {})", ty.name.as_deref().unwrap_or("?"), layouter[handle].alignment);
+        for member in members {
+            let member_layout = &layouter[member.ty];
+            println!(
+                "      {:>4}  {}: {} (size {})",
+                member.offset,
+                member.name.as_deref().unwrap_or("?"),
+                ctx.type_to_string(member.ty),
+                member_layout.size,
+            );
+        }
+    }
+}
+
+fn check_limits(module: &naga::Module, layout: &ShaderLayout, limits: &wgpu::Limits) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for ep in module.entry_points.iter().filter(|ep| ep.stage == naga::ShaderStage::Compute) {
+        let [x, y, z] = ep.workgroup_size;
+        let max = [limits.max_compute_workgroup_size_x, limits.max_compute_workgroup_size_y, limits.max_compute_workgroup_size_z];
+        if x > max[0] || y > max[1] || z > max[2] {
+            problems.push(format!("{}: workgroup size ({x}, {y}, {z}) is larger than ({}, {}, {})", ep.name, max[0], max[1], max[2]));
+        }
+        // u64 so huge sizes are reported as over the limit instead of wrapping
+        let invocations = x as u64 * y as u64 * z as u64;
+        if invocations > limits.max_compute_invocations_per_workgroup as u64 {
+            problems.push(format!("{}: {} invocations per workgroup, the limit is {}", ep.name, invocations, limits.max_compute_invocations_per_workgroup));
+        }
+    }
+
+    if let Some(&group) = layout.groups.keys().next_back()
+        && group >= limits.max_bind_groups {
+        problems.push(format!("uses @group({group}), only {} bind groups are available", limits.max_bind_groups));
+    }
+    for (group, entries) in &layout.groups {
+        for entry in entries.entries().iter().filter(|e| e.binding >= limits.max_bindings_per_bind_group) {
+            problems.push(format!("@group({group}) @binding({}) is past the {} bindings per group", entry.binding, limits.max_bindings_per_bind_group));
+        }
+    }
+
+    let stages = [
+        (wgpu::ShaderStages::VERTEX, "vertex"),
+        (wgpu::ShaderStages::FRAGMENT, "fragment"),
+        (wgpu::ShaderStages::COMPUTE, "compute"),
+    ];
+    for (stage, stage_name) in stages {
+        // uniform buffers, storage buffers, sampled textures, storage textures, samplers
+        let mut counts = [0u32; 5];
+        for entry in layout.groups.values().flat_map(|g| g.entries()).filter(|e| e.visibility.contains(stage)) {
+            let kind = match entry.ty {
+                wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. } => 0,
+                wgpu::BindingType::Buffer { .. } => 1,
+                wgpu::BindingType::Texture { .. } => 2,
+                wgpu::BindingType::StorageTexture { .. } => 3,
+                wgpu::BindingType::Sampler(_) => 4,
+                _ => continue,
+            };
+            counts[kind] += entry.count.map_or(1, |c| c.get());
+        }
+
+        let checks = [
+            ("uniform buffers", counts[0], limits.max_uniform_buffers_per_shader_stage),
+            ("storage buffers", counts[1], limits.max_storage_buffers_per_shader_stage),
+            ("sampled textures", counts[2], limits.max_sampled_textures_per_shader_stage),
+            ("storage textures", counts[3], limits.max_storage_textures_per_shader_stage),
+            ("samplers", counts[4], limits.max_samplers_per_shader_stage),
+        ];
+        for (what, count, max) in checks {
+            if count > max {
+                problems.push(format!("{count} {what} in the {stage_name} stage, the limit is {max}"));
+            }
+        }
+    }
+    problems
+}
+
+/// The name outputs for `file` start with, its file stem unless another input has the same one, like `foo.vert` and
+/// `foo.frag`, in which case the extension is kept so their outputs don't overwrite each other
+fn output_stem(file: &str, files: &[String]) -> String {
+    let stem = |file: &str| Path::new(file).file_stem().and_then(|s| s.to_str()).unwrap_or("shader").to_string();
+    let own = stem(file);
+    let shared = files.iter().any(|other| other != file && stem(other) == own);
+    match Path::new(file).extension().and_then(|e| e.to_str()) {
+        Some(extension) if shared => format!("{own}.{extension}"),
+        _ => own,
+    }
+}
+
+/// Where an output called `name` goes, refusing to overwrite the input it was generated from
+fn output_path(input: &Path, out_dir: Option<&Path>, name: &str) -> Result<PathBuf, String> {
+    let dir = match out_dir.or(input.parent()) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let path = dir.join(name);
+    let same = match (path.canonicalize(), input.canonicalize()) {
+        (Ok(path), Ok(input)) => path == input,
+        _ => path.components().eq(input.components()) || Path::new(".").join(input) == path,
+    };
+    if same {
+        return Err(format!("refusing to overwrite the input {}, pass -o to write somewhere else", input.display()));
+    }
+    Ok(path)
+}
+
+fn emit(format: &str, file: &str, stem: &str, module: &naga::Module, info: &naga::valid::ModuleInfo, out_dir: Option<&Path>) -> Result<Vec<PathBuf>, String> {
+    let input = Path::new(file);
+    let write = |name: String, contents: &[u8]| -> Result<PathBuf, String> {
+        let path = output_path(input, out_dir, &name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&path, contents).map_err(|e| e.to_string())?;
+        Ok(path)
+    };
+
+    match format {
+        "spv" => {
+            let words = naga::back::spv::write_vec(module, info, &naga::back::spv::Options::default(), None)
+                .map_err(|e| e.to_string())?;
+            Ok(vec![write(format!("{stem}.spv"), bytemuck::cast_slice(&words))?])
+        },
+        "msl" => {
+            let (code, _) = naga::back::msl::write_string(module, info, &Default::default(), &Default::default())
+                .map_err(|e| e.to_string())?;
+            Ok(vec![write(format!("{stem}.metal"), code.as_bytes())?])
+        },
+        "rust" => {
+            let code = generate_rust(module).map_err(|e| e.to_string())?;
+            Ok(vec![write(format!("{stem}.rs"), format!("// Generated from {file} by hb-gpu-shaderc\n\n{code}").as_bytes())?])
+        },
+        "hlsl" => {
+            let mut code = String::new();
+            naga::back::hlsl::Writer::new(&mut code, &Default::default(), &Default::default())
+                .write(module, info, None)
+                .map_err(|e| e.to_string())?;
+            Ok(vec![write(format!("{stem}.hlsl"), code.as_bytes())?])
+        },
+        _ => {
+            // GLSL has one entry point per file
+            let options = naga::back::glsl::Options {
+                version: naga::back::glsl::Version::Desktop(450),
+                ..Default::default()
+            };
+            let mut written = Vec::new();
+            for ep in &module.entry_points {
+                let pipeline_options = naga::back::glsl::PipelineOptions {
+                    shader_stage: ep.stage,
+                    entry_point: ep.name.clone(),
+                    multiview: None,
+                };
+                let mut code = String::new();
+                naga::back::glsl::Writer::new(&mut code, module, info, &options, &pipeline_options, Default::default())
+                    .and_then(|mut writer| writer.write())
+                    .map_err(|e| format!("{}: {e}", ep.name))?;
+                written.push(write(format!("{stem}.{}.{}.glsl", ep.name, stage_name(ep.stage)), code.as_bytes())?);
+            }
+            Ok(written)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Args, String> {
+        parse_args(line.split_whitespace().map(str::to_string))
+    }
+
+    fn parse(source: &str) -> (naga::Module, ShaderLayout) {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .unwrap();
+        let layout = ShaderLayout::from_module(&module, &info).unwrap();
+        (module, layout)
+    }
+
+    fn limit_problems(source: &str, limits: &wgpu::Limits) -> Vec<String> {
+        let (module, layout) = parse(source);
+        check_limits(&module, &layout, limits)
+    }
+
+    fn compute(workgroup_size: &str) -> String {
+        format!("@compute @workgroup_size({workgroup_size}) fn main() {{}}")
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = args("-q -D FAST -D COUNT=4 -C 7=3.5 -C enabled=true --limits webgl2 --emit spv,rust -o out a.wgsl b.comp").unwrap();
+        assert!(args.quiet);
+        assert_eq!(args.defs.get("FAST"), Some(""));
+        assert_eq!(args.defs.get("COUNT"), Some("4"));
+        assert_eq!(args.constants, [("7".to_string(), 3.5), ("enabled".to_string(), 1.0)]);
+        assert_eq!(args.limits.0, "webgl2");
+        assert_eq!(args.limits.1.max_storage_buffers_per_shader_stage, 0);
+        assert_eq!(args.emit, ["spv", "rust"]);
+        assert_eq!(args.out_dir, Some(PathBuf::from("out")));
+        assert_eq!(args.files, ["a.wgsl", "b.comp"]);
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        let error = |line: &str| args(line).err().unwrap();
+        assert_eq!(error(""), "no input files");
+        assert_eq!(error("--help a.wgsl"), "");
+        assert_eq!(error("a.wgsl -D"), "-D needs a value");
+        assert_eq!(error("-C fast a.wgsl"), "expected KEY=VALUE, got `fast`");
+        assert_eq!(error("-C fast=yes a.wgsl"), "`yes` isn't a number");
+        assert_eq!(error("--limits huge a.wgsl"), "unknown limits `huge`");
+        assert_eq!(error("--emit spv,wgsl a.wgsl"), "unknown output format `wgsl`");
+        assert_eq!(error("--capabilities FLOAT128 a.wgsl"), "unknown capability `FLOAT128`");
+        assert_eq!(error("--fast a.wgsl"), "unknown option `--fast`");
+    }
+
+    #[test]
+    fn parses_capabilities() {
+        use naga::valid::Capabilities as C;
+        assert_eq!(parse_capabilities("all"), Ok(C::all()));
+        assert_eq!(parse_capabilities("none"), Ok(C::empty()));
+        assert_eq!(parse_capabilities("FLOAT64, CUBE_ARRAY_TEXTURES"), Ok(C::FLOAT64 | C::CUBE_ARRAY_TEXTURES));
+        assert_eq!(parse_capabilities("SHADER_F64"), Err("unknown capability `SHADER_F64`".to_string()));
+    }
+
+    #[test]
+    fn reports_workgroup_limits() {
+        let limits = wgpu::Limits::default();
+        assert_eq!(limit_problems(&compute("256"), &limits), Vec::<String>::new());
+        assert_eq!(limit_problems(&compute("512"), &limits), [
+            "main: workgroup size (512, 1, 1) is larger than (256, 256, 64)",
+            "main: 512 invocations per workgroup, the limit is 256",
+        ]);
+        assert_eq!(limit_problems(&compute("16, 16, 2"), &limits), ["main: 512 invocations per workgroup, the limit is 256"]);
+        assert_eq!(limit_problems(&compute("1, 1, 128"), &limits), ["main: workgroup size (1, 1, 128) is larger than (256, 256, 64)"]);
+    }
+
+    #[test]
+    fn invocation_count_doesnt_overflow() {
+        // naga rejects sizes this large, so set it after validation. 65536 * 65536 * 2 is 0 in u32
+        let (mut module, layout) = parse(&compute("1"));
+        module.entry_points[0].workgroup_size = [65536, 65536, 2];
+        let limits = wgpu::Limits { max_compute_workgroup_size_x: u32::MAX, max_compute_workgroup_size_y: u32::MAX, ..Default::default() };
+        assert_eq!(check_limits(&module, &layout, &limits), ["main: 8589934592 invocations per workgroup, the limit is 256"]);
+    }
+
+    #[test]
+    fn reports_group_and_binding_limits() {
+        let source = "
+@group(4) @binding(0) var<uniform> a: f32;
+@group(0) @binding(1000) var<uniform> b: f32;
+@compute @workgroup_size(1) fn main() { _ = a + b; }
+";
+        assert_eq!(limit_problems(source, &wgpu::Limits::default()), [
+            "uses @group(4), only 4 bind groups are available",
+            "@group(0) @binding(1000) is past the 1000 bindings per group",
+        ]);
+    }
+
+    #[test]
+    fn reports_per_stage_binding_counts() {
+        let source = "
+@group(0) @binding(0) var<uniform> u0: f32;
+@group(0) @binding(1) var<uniform> u1: f32;
+@group(0) @binding(2) var<storage, read> s0: f32;
+@group(0) @binding(3) var<storage, read> s1: f32;
+@group(0) @binding(4) var t0: texture_2d<f32>;
+@group(0) @binding(5) var t1: texture_2d<f32>;
+@group(0) @binding(6) var i0: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(7) var i1: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(8) var p0: sampler;
+@group(0) @binding(9) var p1: sampler;
+
+@compute @workgroup_size(1)
+fn main() {
+    let c = textureSampleLevel(t0, p0, vec2<f32>(0.0), 0.0) + textureSampleLevel(t1, p1, vec2<f32>(0.0), 0.0);
+    textureStore(i0, vec2<i32>(0), c * (u0 + u1));
+    textureStore(i1, vec2<i32>(0), c * (s0 + s1));
+}
+";
+        let limits = wgpu::Limits {
+            max_uniform_buffers_per_shader_stage: 1,
+            max_storage_buffers_per_shader_stage: 1,
+            max_sampled_textures_per_shader_stage: 1,
+            max_storage_textures_per_shader_stage: 1,
+            max_samplers_per_shader_stage: 1,
+            ..Default::default()
+        };
+        assert_eq!(limit_problems(source, &limits), [
+            "2 uniform buffers in the compute stage, the limit is 1",
+            "2 storage buffers in the compute stage, the limit is 1",
+            "2 sampled textures in the compute stage, the limit is 1",
+            "2 storage textures in the compute stage, the limit is 1",
+            "2 samplers in the compute stage, the limit is 1",
+        ]);
+        assert_eq!(limit_problems(source, &wgpu::Limits::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn outputs_keep_the_extension_when_stems_collide() {
+        let files = ["shaders/blur.vert".to_string(), "shaders/blur.frag".to_string(), "tonemap.wgsl".to_string()];
+        assert_eq!(output_stem("shaders/blur.vert", &files), "blur.vert");
+        assert_eq!(output_stem("shaders/blur.frag", &files), "blur.frag");
+        assert_eq!(output_stem("tonemap.wgsl", &files), "tonemap");
+    }
+
+    #[test]
+    fn outputs_never_overwrite_the_input() {
+        let input = Path::new("shaders/blur.spv");
+        assert!(output_path(input, None, "blur.spv").unwrap_err().contains("refusing to overwrite"));
+        assert!(output_path(Path::new("blur.spv"), None, "blur.spv").is_err());
+        assert_eq!(output_path(input, None, "blur.metal"), Ok(PathBuf::from("shaders/blur.metal")));
+        assert_eq!(output_path(input, Some(Path::new("out")), "blur.spv"), Ok(PathBuf::from("out/blur.spv")));
+        assert_eq!(output_path(Path::new("blur.spv"), None, "blur.rs"), Ok(PathBuf::from("./blur.rs")));
+    }
+}
@@ -1,104 +1,533 @@
+use std::fmt;
+use std::num::NonZeroU32;
+
+use crate::gpu::Gpu;
+use crate::reflect::{compare_entries, ShaderLayout};
+use crate::registry::Handle;
+
+pub use hb_gpu_macros::BindGroup;
 use crate::resource::*;
 use crate::buffer::*;
+use crate::sampler::*;
 use crate::texture::*;
 
+/// Resources are kept until `finish` so array bindings can own the slice wgpu borrows from
+enum Resource<'a> {
+    Buffer(wgpu::BufferBinding<'a>),
+    TextureView(&'a wgpu::TextureView),
+    Sampler(&'a wgpu::Sampler),
+    BufferArray(Vec<wgpu::BufferBinding<'a>>),
+    TextureViewArray(Vec<&'a wgpu::TextureView>),
+    // cloned out of the `ResourceManager` when handles are resolved
+    OwnedBuffer(wgpu::Buffer),
+    OwnedTextureView(wgpu::TextureView),
+    OwnedSampler(wgpu::Sampler),
+}
+
+/// A binding added by handle, looked up in the `ResourceManager` passed to `finish`
+enum HandleBinding {
+    Buffer(Handle<Buffer>),
+    Texture(Handle<Texture>),
+    Sampler(Handle<Sampler>),
+}
+
+/// Handle bindings after looking them up, ready to merge with the rest of the builder
+struct ResolvedHandles {
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    resources: Vec<(u32, Resource<'static>)>,
+    sources: Vec<(Generation, u64)>,
+}
+
 pub struct BGBuilder<'a> {
     layout_entries: BindGroupLayoutEntries,
-    entries:        Vec<wgpu::BindGroupEntry<'a>>,
+    resources:      Vec<(u32, Resource<'a>)>,
     device:         &'a wgpu::Device,
+    // used to check adapter specific format features, like which formats support read-write storage
+    adapter:        Option<&'a wgpu::Adapter>,
+    // index set with `binding` for the next resource
+    next_binding:   Option<u32>,
+    label:          Option<String>,
+    // first problem found while adding resources, returned from `finish`
+    error:          Option<BindGroupError>,
+    // generation of every texture and buffer added, so the bind group can tell when one was replaced
+    sources:        Vec<(Generation, u64)>,
+    handles:        Vec<(u32, wgpu::ShaderStages, HandleBinding)>,
+    // every binding index used so far, in the order they were added
+    bindings:       Vec<u32>,
 }
 
-#[derive(Hash, PartialEq, Eq, Clone)]
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
 pub struct BindGroupLayoutEntries {
     entries: Vec<wgpu::BindGroupLayoutEntry>
 }
 
+impl BindGroupLayoutEntries {
+    /// Entries sorted by binding, so layouts that only differ in declaration order hash the same
+    pub fn new(mut entries: Vec<wgpu::BindGroupLayoutEntry>) -> Self {
+        entries.sort_by_key(|e| e.binding);
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, binding: u32) -> Option<&wgpu::BindGroupLayoutEntry> {
+        self.entries.iter().find(|e| e.binding == binding)
+    }
+}
+
 pub struct BindGroup {
     pub raw: wgpu::BindGroup,
     pub entries: BindGroupLayoutEntries,
+    sources: Vec<(Generation, u64)>,
 }
 
+impl BindGroup {
+    /// Whether a texture or buffer this was built from has been resized, grown or destroyed since, meaning the
+    /// group still points at the old GPU object and needs to be built again
+    pub fn is_stale(&self) -> bool {
+        self.sources.iter().any(|(generation, built)| generation.get() != *built)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindGroupError {
+    /// Two resources were given the same binding index
+    DuplicateBinding(u32),
+    /// The bindings don't match the layout declared for them, like the field attributes of `#[derive(BindGroup)]`
+    DeclaredLayoutMismatch { problems: Vec<String> },
+    /// A handle binding points at a resource that was destroyed
+    StaleHandle { binding: u32 },
+    /// The bindings don't match what a shader declares for this group, one readable line per problem
+    LayoutMismatch { group: u32, problems: Vec<String> },
+    /// A binding array was added but the device wasn't created with the feature it needs
+    MissingFeature { binding: u32, feature: wgpu::Features },
+    /// A binding array that is empty or mixes resources that can't share one layout entry
+    InvalidArray { binding: u32, reason: String },
+    /// A resource that can't be bound the way it was added, like a buffer without uniform or storage usage
+    InvalidResource { binding: u32, reason: String },
+}
+
+impl fmt::Display for BindGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindGroupError::DuplicateBinding(binding) => write!(f, "binding {binding} is used more than once"),
+            BindGroupError::DeclaredLayoutMismatch { problems } => write!(f, "bind group doesn't match its declared layout:\n  {}", problems.join("\n  ")),
+            BindGroupError::StaleHandle { binding } => write!(f, "binding {binding} uses a handle to a resource that was destroyed"),
+            BindGroupError::LayoutMismatch { group, problems } => write!(f, "bind group {group} doesn't match the shader:\n  {}", problems.join("\n  ")),
+            BindGroupError::MissingFeature { binding, feature } => write!(f, "binding {binding} is an array but the device doesn't have {feature:?} enabled"),
+            BindGroupError::InvalidArray { binding, reason } => write!(f, "invalid binding array at binding {binding}: {reason}"),
+            BindGroupError::InvalidResource { binding, reason } => write!(f, "invalid resource for binding {binding}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BindGroupError {}
+
+/// Sampled binding type for a view, also used by `BindlessTable` to check registered textures fit its array
+pub(crate) fn texture_binding_type(view: &TextureView) -> wgpu::BindingType {
+    let multisampled = view.raw.texture().sample_count() > 1;
+    let sample_type = match view.format.sample_type(None, None).unwrap_or_default() {
+        // multisampled textures are read with textureLoad, wgpu rejects filterable multisampled bindings
+        wgpu::TextureSampleType::Float { .. } if multisampled => wgpu::TextureSampleType::Float { filterable: false },
+        sample_type => sample_type,
+    };
+
+    wgpu::BindingType::Texture {
+        sample_type,
+        view_dimension: view.dimension,
+        multisampled,
+    }
+}
+
+fn buffer_binding_type(view: &BufferView) -> Result<wgpu::BindingType, String> {
+    let ty : wgpu::BufferBindingType = match view.buffer.raw.usage() {
+        _ if view.buffer.raw.usage().contains(wgpu::BufferUsages::UNIFORM)  => wgpu::BufferBindingType::Uniform,
+        _ if view.buffer.raw.usage().contains(wgpu::BufferUsages::STORAGE)  => wgpu::BufferBindingType::Storage { read_only: view.read_only },
+        _ => return Err("the buffer was created without UNIFORM or STORAGE usage".to_string()),
+    };
+
+    Ok(wgpu::BindingType::Buffer { ty, has_dynamic_offset: false, min_binding_size: None })
+}
 
 impl<'a> BGBuilder<'a> {
-    pub fn new(device: &wgpu::Device) -> BGBuilder {
+    pub fn new(device: &wgpu::Device) -> BGBuilder<'_> {
         BGBuilder {
-            device: &device,
-            entries: Vec::new(),
+            device,
+            adapter: None,
+            resources: Vec::new(),
             layout_entries: BindGroupLayoutEntries{entries: Vec::new()},
+            next_binding: None,
+            label: None,
+            error: None,
+            sources: Vec::new(),
+            handles: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Label the bind group, and its layout when this builder is the one that creates it
+    pub fn with_label(&mut self, label: &str) -> &mut Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Put the next resource at `index` instead of one after the previous binding.
+    /// Later resources continue counting from there, so gaps are left as they are
+    pub fn binding(&mut self, index: u32) -> &mut Self {
+        self.next_binding = Some(index);
+        self
+    }
+
+    /// Index for the next resource, defaulting to one past the last one added
+    fn take_binding(&mut self) -> u32 {
+        let last = self.bindings.last();
+        self.next_binding.take().unwrap_or(last.map_or(0, |b| b + 1))
+    }
+
+    fn claim(&mut self, binding: u32) {
+        if self.bindings.contains(&binding) {
+            self.fail(BindGroupError::DuplicateBinding(binding));
         }
+        self.bindings.push(binding);
     }
 
+    fn fail(&mut self, error: BindGroupError) {
+        self.error.get_or_insert(error);
+    }
+
+    /// Record an invalid resource, still claiming its binding so the ones after it keep their indices
+    fn reject(&mut self, binding: u32, reason: String) -> &mut Self {
+        self.claim(binding);
+        self.fail(BindGroupError::InvalidResource { binding, reason });
+        self
+    }
+
+    fn push(&mut self, binding: u32, visibility: wgpu::ShaderStages, ty: wgpu::BindingType, count: Option<NonZeroU32>, resource: Resource<'a>) {
+        self.claim(binding);
+
+        self.layout_entries.entries.push(wgpu::BindGroupLayoutEntry { binding, visibility, ty, count });
+        self.resources.push((binding, resource));
+    }
+
+    fn track(&mut self, generation: &Generation) {
+        self.sources.push((generation.clone(), generation.get()));
+    }
+
+    fn require_feature(&mut self, binding: u32, feature: wgpu::Features) {
+        if !self.device.features().contains(feature) {
+            self.fail(BindGroupError::MissingFeature { binding, feature });
+        }
+    }
+
+    /// Add a uniform or storage buffer binding, picked from the buffer's usage.
+    /// Fails in `finish` if the buffer has neither usage
     pub fn with_buffer(&mut self, view: &'a BufferView, visibility: wgpu::ShaderStages) -> &mut Self {
-        let ty : wgpu::BufferBindingType = match view.buffer.raw.usage() {
-            _ if view.buffer.raw.usage().contains(wgpu::BufferUsages::UNIFORM)  => wgpu::BufferBindingType::Uniform,
-            _ if view.buffer.raw.usage().contains(wgpu::BufferUsages::STORAGE)  => wgpu::BufferBindingType::Storage { read_only: false },
-            _ => panic!("Invalid buffer usage: expected uniform or storage"),
+        let binding = self.take_binding();
+        let ty = match buffer_binding_type(view) {
+            Ok(ty) => ty,
+            Err(reason) => return self.reject(binding, reason),
         };
+        self.track(&view.buffer.generation);
+        self.push(binding, visibility, ty, None, Resource::Buffer(view.binding()));
+        self
+    }
 
-        let layout_entry = wgpu::BindGroupLayoutEntry {
-            binding: self.layout_entries.entries.len() as u32,
-            count: None,
-            visibility,
-            ty: wgpu::BindingType::Buffer { ty, has_dynamic_offset: false, min_binding_size: None }
-        };
+    pub fn with_texture(&mut self, texture: &'a Texture, visibility: wgpu::ShaderStages) -> &mut Self {
+        self.with_texture_view(texture.view_all(), visibility)
+    }
+
+    /// Add a sampled texture binding for a specific view, like a single layer or mip range
+    pub fn with_texture_view(&mut self, view: &'a TextureView, visibility: wgpu::ShaderStages) -> &mut Self {
+        let binding = self.take_binding();
+        let ty = texture_binding_type(view);
+        self.track(&view.generation);
+        self.push(binding, visibility, ty, None, Resource::TextureView(&view.raw));
+        self
+    }
+
+    /// Bind several textures as a `binding_array<texture_*, N>`, where N is `textures.len()`
+    ///
+    /// Needs `Features::TEXTURE_BINDING_ARRAY`, and every texture must have the same sample type, dimension and sample count
+    pub fn with_texture_array(&mut self, textures: &[&'a Texture], visibility: wgpu::ShaderStages) -> &mut Self {
+        let views: Vec<&'a TextureView> = textures.iter().map(|t| t.view_all()).collect();
+        self.with_texture_view_array(&views, visibility)
+    }
 
-        self.layout_entries.entries.push(layout_entry);
+    /// Same as `with_texture_array`, for specific views instead of each texture's full view
+    pub fn with_texture_view_array(&mut self, views: &[&'a TextureView], visibility: wgpu::ShaderStages) -> &mut Self {
+        let binding = self.take_binding();
+        self.require_feature(binding, wgpu::Features::TEXTURE_BINDING_ARRAY);
 
-        let entry = wgpu::BindGroupEntry {
-            binding: self.entries.len() as u32,
-            resource: wgpu::BindingResource::Buffer(view.binding()),
+        let Some(first) = views.first() else {
+            self.fail(BindGroupError::InvalidArray { binding, reason: "binding arrays can't be empty".into() });
+            return self;
         };
-        self.entries.push(entry);
+        let ty = texture_binding_type(first);
+        if let Some((i, other)) = views.iter().enumerate().find(|(_, v)| texture_binding_type(v) != ty) {
+            let reason = format!("element {i} is a {:?} {:?} view, which doesn't have the same binding type as the {:?} {:?} first element",
+                other.format, other.dimension, first.format, first.dimension);
+            self.fail(BindGroupError::InvalidArray { binding, reason });
+        }
+        for view in views {
+            self.track(&view.generation);
+        }
+        let raw_views = views.iter().map(|v| &v.raw).collect();
+        self.push(binding, visibility, ty, NonZeroU32::new(views.len() as u32), Resource::TextureViewArray(raw_views));
         self
     }
 
-    pub fn with_texture(&mut self, texture: &'a Texture, visibility: wgpu::ShaderStages) -> &mut Self {
-        
-        let ty = wgpu::BindingType::Texture {
-            sample_type: texture.raw.format().sample_type(None, None).unwrap_or_default(), 
-            view_dimension: texture.dim, 
-            multisampled: false // no multisampling for now
+    /// Bind several buffers as a `binding_array<T, N>`, where N is `views.len()`
+    ///
+    /// Needs `Features::BUFFER_BINDING_ARRAY`, plus `Features::STORAGE_RESOURCE_BINDING_ARRAY` for storage buffers.
+    /// All buffers must be uniform or all storage
+    pub fn with_buffer_array(&mut self, views: &[&BufferView<'a>], visibility: wgpu::ShaderStages) -> &mut Self {
+        let binding = self.take_binding();
+        self.require_feature(binding, wgpu::Features::BUFFER_BINDING_ARRAY);
+
+        let Some(first) = views.first() else {
+            self.fail(BindGroupError::InvalidArray { binding, reason: "binding arrays can't be empty".into() });
+            return self;
         };
+        let ty = match buffer_binding_type(first) {
+            Ok(ty) => ty,
+            Err(reason) => return self.reject(binding, reason),
+        };
+        if views.iter().any(|v| buffer_binding_type(v) != Ok(ty)) {
+            self.fail(BindGroupError::InvalidArray { binding, reason: "uniform and storage buffers can't share a binding array".into() });
+        }
+        if let wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { .. }, .. } = ty {
+            self.require_feature(binding, wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY);
+        }
+
+        for view in views {
+            self.track(&view.buffer.generation);
+        }
+        let buffers = views.iter().map(|v| v.binding()).collect();
+        self.push(binding, visibility, ty, NonZeroU32::new(views.len() as u32), Resource::BufferArray(buffers));
+        self
+    }
 
-        let layout_entry = wgpu::BindGroupLayoutEntry {
-            binding: self.layout_entries.entries.len() as u32,
-            count: None,
-            visibility,
-            ty
+    /// Add a texture from the `ResourceManager` by handle, bound like `with_texture`. The handle is resolved in `finish`
+    pub fn with_texture_handle(&mut self, texture: Handle<Texture>, visibility: wgpu::ShaderStages) -> &mut Self {
+        self.push_handle(visibility, HandleBinding::Texture(texture))
+    }
+
+    /// Add a buffer from the `ResourceManager` by handle, bound whole like `with_buffer`
+    pub fn with_buffer_handle(&mut self, buffer: Handle<Buffer>, visibility: wgpu::ShaderStages) -> &mut Self {
+        self.push_handle(visibility, HandleBinding::Buffer(buffer))
+    }
+
+    /// Add a sampler from the `ResourceManager` by handle, bound like `with_sampler`
+    pub fn with_sampler_handle(&mut self, sampler: Handle<Sampler>, visibility: wgpu::ShaderStages) -> &mut Self {
+        self.push_handle(visibility, HandleBinding::Sampler(sampler))
+    }
+
+    fn push_handle(&mut self, visibility: wgpu::ShaderStages, handle: HandleBinding) -> &mut Self {
+        let binding = self.take_binding();
+        self.claim(binding);
+        self.handles.push((binding, visibility, handle));
+        self
+    }
+
+    /// Look up every handle binding, returning their layout entries and resources
+    fn resolve_handles(&self, manager: &ResourceManager) -> Result<ResolvedHandles, BindGroupError> {
+        let mut resolved = ResolvedHandles {
+            layout_entries: Vec::new(),
+            resources: Vec::new(),
+            sources: Vec::new(),
         };
+        for (binding, visibility, handle) in &self.handles {
+            let binding = *binding;
+            let stale = || BindGroupError::StaleHandle { binding };
+            let (ty, resource) = match handle {
+                HandleBinding::Buffer(handle) => {
+                    let buffer = manager.get(*handle).ok_or_else(stale)?;
+                    resolved.sources.push((buffer.generation.clone(), buffer.generation.get()));
+                    let ty = buffer_binding_type(&buffer.view_all()).map_err(|reason| BindGroupError::InvalidResource { binding, reason })?;
+                    (ty, Resource::OwnedBuffer(buffer.raw.clone()))
+                },
+                HandleBinding::Texture(handle) => {
+                    let view = manager.get(*handle).ok_or_else(stale)?.view_all();
+                    resolved.sources.push((view.generation.clone(), view.generation.get()));
+                    (texture_binding_type(view), Resource::OwnedTextureView(view.raw.clone()))
+                },
+                HandleBinding::Sampler(handle) => {
+                    let sampler = manager.get(*handle).ok_or_else(stale)?;
+                    (wgpu::BindingType::Sampler(sampler.desc.binding_type()), Resource::OwnedSampler(sampler.raw.clone()))
+                },
+            };
+            resolved.layout_entries.push(wgpu::BindGroupLayoutEntry { binding, visibility: *visibility, ty, count: None });
+            resolved.resources.push((binding, resource));
+        }
+        Ok(resolved)
+    }
+
+    /// Check storage texture format support against this adapter instead of the guaranteed WebGPU formats
+    pub fn with_adapter(&mut self, adapter: &'a wgpu::Adapter) -> &mut Self {
+        self.adapter = Some(adapter);
+        self
+    }
+
+    /// Add a storage texture binding for a single mip level view
+    ///
+    /// Fails in `finish` if the texture wasn't created with `STORAGE_BINDING`, the view covers more than one mip level
+    /// or is a cube view, or the format doesn't support `access` on the current adapter
+    pub fn with_storage_texture(&mut self, view: &'a TextureView, access: wgpu::StorageTextureAccess, visibility: wgpu::ShaderStages) -> &mut Self {
+        let texture = view.raw.texture();
+        let binding = self.take_binding();
+        if !texture.usage().contains(wgpu::TextureUsages::STORAGE_BINDING) {
+            return self.reject(binding, "the texture was created without STORAGE_BINDING usage".to_string());
+        }
+
+        let mip_levels = view.mip_level_count.unwrap_or(texture.mip_level_count() - view.base_mip_level);
+        if mip_levels != 1 {
+            return self.reject(binding, format!("the storage view covers {mip_levels} mip levels, use Texture::new_mip_view"));
+        }
 
-        self.layout_entries.entries.push(layout_entry);
+        if matches!(view.dimension, wgpu::TextureViewDimension::Cube | wgpu::TextureViewDimension::CubeArray) {
+            return self.reject(binding, "cube views can't be storage bound, use a D2Array view".to_string());
+        }
 
-        let entry = wgpu::BindGroupEntry {
-            binding: self.entries.len() as u32,
-            resource: wgpu::BindingResource::TextureView(&texture.view_all().raw)
+        // the device only honors adapter specific format features when it was created with the feature enabled
+        let device_features = self.device.features();
+        let flags = match self.adapter {
+            Some(adapter) if device_features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) => {
+                adapter.get_texture_format_features(view.format).flags
+            },
+            _ => view.format.guaranteed_format_features(device_features).flags,
         };
+        let required = match access {
+            wgpu::StorageTextureAccess::ReadOnly => wgpu::TextureFormatFeatureFlags::STORAGE_READ_ONLY,
+            wgpu::StorageTextureAccess::WriteOnly => wgpu::TextureFormatFeatureFlags::STORAGE_WRITE_ONLY,
+            wgpu::StorageTextureAccess::ReadWrite => wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE,
+            wgpu::StorageTextureAccess::Atomic => wgpu::TextureFormatFeatureFlags::STORAGE_ATOMIC,
+        };
+        if !flags.contains(required) {
+            return self.reject(binding, format!("{:?} does not support {access:?} storage access on this adapter", view.format));
+        }
 
-        self.entries.push(entry);
+        let ty = wgpu::BindingType::StorageTexture {
+            access,
+            format: view.format,
+            view_dimension: view.dimension,
+        };
+        self.track(&view.generation);
+        self.push(binding, visibility, ty, None, Resource::TextureView(&view.raw));
         self
     }
 
-    pub fn finish<'b>(&mut self, manager: &'b mut ResourceManager) -> BindGroup {
-        if !manager.bind_group_layouts.contains_key(&self.layout_entries) {
-            println!("Created new bind group layout");
-            let layout_desc = wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: self.layout_entries.entries.as_slice(),
-            };
-            manager.bind_group_layouts.insert(self.layout_entries.clone(), self.device.create_bind_group_layout(&layout_desc));
+    /// Add a sampler, using a filtering, non-filtering or comparison binding to match it.
+    /// It isn't checked against any texture, use `with_sampler_for` when the texture it samples is known
+    pub fn with_sampler(&mut self, sampler: &'a Sampler, visibility: wgpu::ShaderStages) -> &mut Self {
+        let binding = self.take_binding();
+        let ty = wgpu::BindingType::Sampler(sampler.desc.binding_type());
+        self.push(binding, visibility, ty, None, Resource::Sampler(&sampler.raw));
+        self
+    }
+
+    /// Add a sampler like `with_sampler`, checking it can sample `view`, which doesn't have to be in this bind group.
+    /// Fails in `finish` if it can't, for example a linear sampler for an unfilterable `R32Float` texture or a
+    /// comparison sampler for a color texture
+    pub fn with_sampler_for(&mut self, view: &TextureView, sampler: &'a Sampler, visibility: wgpu::ShaderStages) -> &mut Self {
+        let binding = self.take_binding();
+        if let wgpu::BindingType::Texture { sample_type, .. } = texture_binding_type(view)
+            && let Err(e) = sampler.desc.check_compatible(sample_type) {
+            return self.reject(binding, e);
         }
 
-        let layout = manager.get_bind_group_layout(&self.layout_entries).expect("hash get failed after insertion");
+        let ty = wgpu::BindingType::Sampler(sampler.desc.binding_type());
+        self.push(binding, visibility, ty, None, Resource::Sampler(&sampler.raw));
+        self
+    }
+
+    /// Check the bindings added so far against what `shader` declares for `group`
+    ///
+    /// Every binding the shader uses must be present with a matching type and visibility, extra bindings are allowed.
+    /// Float textures and samplers match regardless of filterability, since WGSL doesn't say which one it needs.
+    /// Handle bindings aren't looked up until `finish`, so they aren't checked here
+    pub fn validate(&self, shader: &ShaderLayout, group: u32) -> Result<(), BindGroupError> {
+        shader.check(group, &self.layout_entries)
+    }
+
+    /// Check the bindings added so far are exactly `expected`, so the bind group is compatible with pipeline
+    /// layouts created from it. Handle bindings aren't looked up until `finish`, so they aren't checked here
+    pub fn check_layout(&self, expected: &BindGroupLayoutEntries) -> Result<(), BindGroupError> {
+        let problems = compare_entries(expected, &BindGroupLayoutEntries::new(self.layout_entries.entries.clone()), true);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(BindGroupError::DeclaredLayoutMismatch { problems })
+        }
+    }
+
+    /// Create the bind group, reusing a cached layout when one with the same entries exists
+    ///
+    /// Fails with the first problem found while adding resources, like two resources sharing a binding index, a
+    /// binding array needing a feature the device doesn't have or a resource that can't be bound the way it was added
+    pub fn finish(&mut self, manager: &mut ResourceManager) -> Result<BindGroup, BindGroupError> {
+        if let Some(error) = self.error.clone() {
+            return Err(error);
+        }
+
+        let resolved = self.resolve_handles(manager)?;
+        let mut sources = self.sources.clone();
+        sources.extend(resolved.sources);
+
+        // the same bindings added in a different order should share a layout
+        let mut layout_entries = self.layout_entries.entries.clone();
+        layout_entries.extend(resolved.layout_entries);
+        let layout_entries = BindGroupLayoutEntries::new(layout_entries);
+        let label = self.label.as_ref().map(|l| format!("{l} layout"));
+        let layout = manager.get_or_create_bind_group_layout(self.device, &layout_entries, label.as_deref());
+
+        let entries: Vec<wgpu::BindGroupEntry> = self.resources.iter().chain(&resolved.resources)
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: *binding,
+                resource: match resource {
+                    Resource::Buffer(buffer) => wgpu::BindingResource::Buffer(buffer.clone()),
+                    Resource::TextureView(view) => wgpu::BindingResource::TextureView(view),
+                    Resource::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+                    Resource::BufferArray(buffers) => wgpu::BindingResource::BufferArray(buffers),
+                    Resource::TextureViewArray(views) => wgpu::BindingResource::TextureViewArray(views),
+                    Resource::OwnedBuffer(buffer) => buffer.as_entire_binding(),
+                    Resource::OwnedTextureView(view) => wgpu::BindingResource::TextureView(view),
+                    Resource::OwnedSampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+                },
+            })
+            .collect();
 
         let desc = wgpu::BindGroupDescriptor {
-            label: None,
+            label: self.label.as_deref(),
             layout,
-            entries: self.entries.as_slice(),
+            entries: &entries,
         };
 
-        BindGroup {
+        Ok(BindGroup {
             raw: self.device.create_bind_group(&desc),
-            entries: self.layout_entries.clone()
-        }
+            entries: layout_entries,
+            sources,
+        })
+    }
+}
+
+/// A struct whose fields make up a bind group, usually implemented with `#[derive(BindGroup)]`
+///
+/// The derive reads `#[uniform(N)]`, `#[storage(N, read_only)]`, `#[texture(N)]` and `#[sampler(N)]` field
+/// attributes, see the `hb-gpu-macros` crate for their options
+pub trait AsBindGroup {
+    /// The layout declared by the field attributes
+    fn layout_entries() -> BindGroupLayoutEntries;
+
+    /// WGSL declarations matching `layout_entries`, placed in `group`
+    fn wgsl(group: u32) -> String;
+
+    /// Bind the field values, failing with `BindGroupError::DeclaredLayoutMismatch` if they don't fit the declared layout
+    fn bind_group(&self, gpu: &Gpu, resources: &mut ResourceManager) -> Result<BindGroup, BindGroupError>;
+
+    /// The cached layout for `layout_entries`, to build pipeline layouts before any bind group exists
+    fn bind_group_layout<'r>(gpu: &Gpu, resources: &'r mut ResourceManager) -> &'r wgpu::BindGroupLayout {
+        resources.get_or_create_bind_group_layout(&gpu.device, &Self::layout_entries(), None)
     }
 }
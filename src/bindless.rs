@@ -0,0 +1,460 @@
+use std::fmt;
+
+use glam::UVec2;
+
+use crate::{bindgroup::{texture_binding_type, BindGroup, BindGroupError}, buffer::Buffer, gpu::Gpu, registry::Handle, resource::ResourceManager, texture::{Texture, TextureView}};
+
+/// How a `BindlessTable` exposes its resources to shaders
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BindlessMode {
+    /// One bind group with `binding_array`s, shaders index them with the slot id
+    Array,
+    /// A small bind group per slot for adapters without binding arrays or non-uniform indexing,
+    /// bound before each draw that uses the slot
+    PerSlot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindlessError {
+    /// Every texture or buffer slot is registered
+    Full { what: &'static str, capacity: u32 },
+    /// The slot isn't registered
+    UnknownSlot { what: &'static str, slot: u32 },
+    /// The resource can't go in the table, one readable line saying why
+    Invalid(String),
+}
+
+impl fmt::Display for BindlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindlessError::Full { what, capacity } => write!(f, "bindless {what} table is full, all {capacity} slots are registered"),
+            BindlessError::UnknownSlot { what, slot } => write!(f, "bindless {what} slot {slot} isn't registered"),
+            BindlessError::Invalid(reason) => write!(f, "invalid bindless resource: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BindlessError {}
+
+/// What a slot points at
+enum Slot<T, R> {
+    /// Registered by reference, with the generation it had then
+    Direct(T, u64),
+    /// Looked up in the `ResourceManager` every time the bind groups are built, so it follows resizes
+    Handle(Handle<R>),
+}
+
+/// Fixed size slots that are handed out and recycled through a free list
+struct Slots<T> {
+    entries: Vec<Option<T>>,
+    free: Vec<u32>,
+    capacity: u32,
+}
+
+impl<T> Slots<T> {
+    fn new(capacity: u32) -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, value: T, what: &'static str) -> Result<u32, BindlessError> {
+        if let Some(slot) = self.free.pop() {
+            self.entries[slot as usize] = Some(value);
+            return Ok(slot);
+        }
+        if self.entries.len() as u32 >= self.capacity {
+            return Err(BindlessError::Full { what, capacity: self.capacity });
+        }
+        self.entries.push(Some(value));
+        Ok(self.entries.len() as u32 - 1)
+    }
+
+    fn replace(&mut self, slot: u32, value: T, what: &'static str) -> Result<(), BindlessError> {
+        match self.entries.get_mut(slot as usize) {
+            Some(entry @ Some(_)) => {
+                *entry = Some(value);
+                Ok(())
+            },
+            _ => Err(BindlessError::UnknownSlot { what, slot }),
+        }
+    }
+
+    fn remove(&mut self, slot: u32) -> bool {
+        match self.entries.get_mut(slot as usize) {
+            Some(entry @ Some(_)) => {
+                *entry = None;
+                self.free.push(slot);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn get(&self, slot: u32) -> Option<&T> {
+        self.entries.get(slot as usize)?.as_ref()
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+}
+
+/// Bindless buffers are bound as read-only storage, but `BGBuilder` binds anything with `UNIFORM` usage as a uniform
+fn check_buffer_usage(usage: wgpu::BufferUsages) -> Result<(), BindlessError> {
+    if !usage.contains(wgpu::BufferUsages::STORAGE) {
+        return Err(BindlessError::Invalid("buffer was created without STORAGE usage".to_string()));
+    }
+    if usage.contains(wgpu::BufferUsages::UNIFORM) {
+        return Err(BindlessError::Invalid("buffer has UNIFORM usage, so it would be bound as a uniform instead of storage".to_string()));
+    }
+    Ok(())
+}
+
+/// A table of textures and storage buffers that shaders look up by slot id
+///
+/// In `BindlessMode::Array` the whole table is one bind group, declared in WGSL as
+/// `@binding(0) var textures: binding_array<texture_2d<f32>, TEXTURE_CAPACITY>` and
+/// `@binding(1) var<storage, read> buffers: binding_array<T, BUFFER_CAPACITY>`, each left out when its capacity is zero. Empty slots point at a
+/// 1x1 white texture and a zeroed buffer, so every index is safe to read.
+///
+/// Adapters without `TEXTURE_BINDING_ARRAY` and non-uniform indexing get `BindlessMode::PerSlot`, where each slot
+/// has its own bind group with the resource at binding 0. `texture_bind_group` and `buffer_bind_group` work in
+/// both modes, returning the shared table in `Array` mode, so callers can bind per draw either way
+///
+/// Resources registered by handle are looked up again whenever one of them is resized or destroyed. Ones registered
+/// by reference can't follow a resize, their slot shows the placeholder until `update_texture` or `update_buffer`
+/// points it at the new object
+pub struct BindlessTable {
+    pub mode: BindlessMode,
+    visibility: wgpu::ShaderStages,
+    textures: Slots<Slot<TextureView, Texture>>,
+    buffers: Slots<Slot<Buffer, Buffer>>,
+    placeholder_texture: Texture,
+    placeholder_buffer: Buffer,
+    table: Option<BindGroup>,
+    texture_groups: Vec<Option<BindGroup>>,
+    buffer_groups: Vec<Option<BindGroup>>,
+}
+
+impl BindlessTable {
+    /// Create a table with room for `texture_capacity` textures and `buffer_capacity` storage buffers
+    ///
+    /// Uses `BindlessMode::Array` when the device supports binding arrays of this size, `PerSlot` otherwise
+    pub fn new(gpu: &Gpu, texture_capacity: u32, buffer_capacity: u32, visibility: wgpu::ShaderStages) -> Self {
+        let features = gpu.device.features();
+        let mut required = wgpu::Features::TEXTURE_BINDING_ARRAY
+            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING;
+        if buffer_capacity > 0 {
+            required |= wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY;
+        }
+        let max_elements = gpu.device.limits().max_binding_array_elements_per_shader_stage;
+        let fits = texture_capacity.checked_add(buffer_capacity).is_some_and(|total| total <= max_elements);
+        let mode = if features.contains(required) && fits {
+            BindlessMode::Array
+        } else {
+            BindlessMode::PerSlot
+        };
+
+        let placeholder_texture = gpu.new_texture(UVec2::ONE, wgpu::TextureFormat::Rgba8UnormSrgb, false);
+        gpu.queue.write_texture(
+            placeholder_texture.raw.as_image_copy(),
+            &[255; 4],
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: None,
+            },
+            placeholder_texture.raw.size(),
+        );
+
+        Self {
+            mode,
+            visibility,
+            textures: Slots::new(texture_capacity),
+            buffers: Slots::new(buffer_capacity),
+            placeholder_texture,
+            placeholder_buffer: gpu.new_storage_buffer(256),
+            table: None,
+            texture_groups: Vec::new(),
+            buffer_groups: Vec::new(),
+        }
+    }
+
+    pub fn texture_capacity(&self) -> u32 {
+        self.textures.capacity
+    }
+
+    pub fn buffer_capacity(&self) -> u32 {
+        self.buffers.capacity
+    }
+
+    /// Number of registered textures
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    /// Number of registered buffers
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    fn check_texture(&self, view: &TextureView) -> Result<(), BindlessError> {
+        let expected = texture_binding_type(self.placeholder_texture.view_all());
+        if texture_binding_type(view) != expected {
+            return Err(BindlessError::Invalid(format!("{:?} {:?} view doesn't fit a filterable texture_2d<f32> table", view.format, view.dimension)));
+        }
+        Ok(())
+    }
+
+    fn check_buffer(buffer: &Buffer) -> Result<(), BindlessError> {
+        check_buffer_usage(buffer.raw.usage())
+    }
+
+    fn texture_slot(&self, texture: &Texture) -> Result<Slot<TextureView, Texture>, BindlessError> {
+        let view = texture.view_all();
+        self.check_texture(view)?;
+        Ok(Slot::Direct(view.clone(), view.generation.get()))
+    }
+
+    fn texture_handle_slot(&self, resources: &ResourceManager, texture: Handle<Texture>) -> Result<Slot<TextureView, Texture>, BindlessError> {
+        let view = resources.get(texture).ok_or_else(|| BindlessError::Invalid(format!("{texture:?} was destroyed")))?.view_all();
+        self.check_texture(view)?;
+        Ok(Slot::Handle(texture))
+    }
+
+    fn buffer_slot(buffer: &Buffer) -> Result<Slot<Buffer, Buffer>, BindlessError> {
+        Self::check_buffer(buffer)?;
+        Ok(Slot::Direct(buffer.clone(), buffer.generation.get()))
+    }
+
+    fn buffer_handle_slot(resources: &ResourceManager, buffer: Handle<Buffer>) -> Result<Slot<Buffer, Buffer>, BindlessError> {
+        Self::check_buffer(resources.get(buffer).ok_or_else(|| BindlessError::Invalid(format!("{buffer:?} was destroyed")))?)?;
+        Ok(Slot::Handle(buffer))
+    }
+
+    /// Add a texture and return its slot id, reusing slots freed by `unregister_texture`.
+    /// Fails if the table is full, or the texture isn't a single sampled 2D texture with a filterable float format
+    pub fn register_texture(&mut self, texture: &Texture) -> Result<u32, BindlessError> {
+        let slot = self.textures.insert(self.texture_slot(texture)?, "texture")?;
+        self.invalidate_texture(slot);
+        Ok(slot)
+    }
+
+    /// Like `register_texture` for a texture in the `ResourceManager`, which the slot follows through resizes
+    pub fn register_texture_handle(&mut self, resources: &ResourceManager, texture: Handle<Texture>) -> Result<u32, BindlessError> {
+        let slot = self.textures.insert(self.texture_handle_slot(resources, texture)?, "texture")?;
+        self.invalidate_texture(slot);
+        Ok(slot)
+    }
+
+    /// Point an existing slot at a different texture, or the same one after it was resized.
+    /// Fails if `slot` isn't registered, or the texture doesn't fit the table like in `register_texture`
+    pub fn update_texture(&mut self, slot: u32, texture: &Texture) -> Result<(), BindlessError> {
+        self.textures.replace(slot, self.texture_slot(texture)?, "texture")?;
+        self.invalidate_texture(slot);
+        Ok(())
+    }
+
+    /// Free a slot for reuse, returns false if it wasn't registered. The slot shows the placeholder until it is reused
+    pub fn unregister_texture(&mut self, slot: u32) -> bool {
+        let removed = self.textures.remove(slot);
+        if removed {
+            self.invalidate_texture(slot);
+        }
+        removed
+    }
+
+    /// Add a storage buffer and return its slot id, it is bound read-only.
+    /// Fails if the table is full, the buffer doesn't have `STORAGE` usage or it also has `UNIFORM` usage
+    pub fn register_buffer(&mut self, buffer: &Buffer) -> Result<u32, BindlessError> {
+        let slot = self.buffers.insert(Self::buffer_slot(buffer)?, "buffer")?;
+        self.invalidate_buffer(slot);
+        Ok(slot)
+    }
+
+    /// Like `register_buffer` for a buffer in the `ResourceManager`, which the slot follows through `Gpu::grow_buffer`
+    pub fn register_buffer_handle(&mut self, resources: &ResourceManager, buffer: Handle<Buffer>) -> Result<u32, BindlessError> {
+        let slot = self.buffers.insert(Self::buffer_handle_slot(resources, buffer)?, "buffer")?;
+        self.invalidate_buffer(slot);
+        Ok(slot)
+    }
+
+    /// Point an existing slot at a different buffer, or the same one after `Gpu::grow_buffer`.
+    /// Fails if `slot` isn't registered or the buffer doesn't fit the table like in `register_buffer`
+    pub fn update_buffer(&mut self, slot: u32, buffer: &Buffer) -> Result<(), BindlessError> {
+        self.buffers.replace(slot, Self::buffer_slot(buffer)?, "buffer")?;
+        self.invalidate_buffer(slot);
+        Ok(())
+    }
+
+    pub fn unregister_buffer(&mut self, slot: u32) -> bool {
+        let removed = self.buffers.remove(slot);
+        if removed {
+            self.invalidate_buffer(slot);
+        }
+        removed
+    }
+
+    /// The view a texture slot binds right now, the placeholder if it's empty or its texture was replaced or destroyed
+    fn resolve_texture(&self, slot: u32, resources: &ResourceManager) -> TextureView {
+        let view = match self.textures.get(slot) {
+            Some(Slot::Direct(view, registered)) if view.generation.get() == *registered => Some(view),
+            Some(Slot::Handle(handle)) => resources.get(*handle).map(Texture::view_all),
+            _ => None,
+        };
+        view.unwrap_or(self.placeholder_texture.view_all()).clone()
+    }
+
+    /// Like `resolve_texture`, for buffer slots
+    fn resolve_buffer(&self, slot: u32, resources: &ResourceManager) -> Buffer {
+        let buffer = match self.buffers.get(slot) {
+            Some(Slot::Direct(buffer, registered)) if buffer.generation.get() == *registered => Some(buffer),
+            Some(Slot::Handle(handle)) => resources.get(*handle),
+            _ => None,
+        };
+        buffer.unwrap_or(&self.placeholder_buffer).clone()
+    }
+
+    fn invalidate_texture(&mut self, slot: u32) {
+        self.table = None;
+        if let Some(group) = self.texture_groups.get_mut(slot as usize) {
+            *group = None;
+        }
+    }
+
+    fn invalidate_buffer(&mut self, slot: u32) {
+        self.table = None;
+        if let Some(group) = self.buffer_groups.get_mut(slot as usize) {
+            *group = None;
+        }
+    }
+
+    /// The bind group holding `slot`: the whole table in `Array` mode, or a group with just that texture in `PerSlot` mode.
+    /// Unregistered slots get the placeholder texture
+    pub fn texture_bind_group(&mut self, gpu: &Gpu, resources: &mut ResourceManager, slot: u32) -> Result<&BindGroup, BindGroupError> {
+        if self.mode == BindlessMode::Array {
+            return self.table(gpu, resources);
+        }
+
+        let index = slot as usize;
+        if self.texture_groups.len() <= index {
+            self.texture_groups.resize_with(index + 1, || None);
+        }
+        if self.texture_groups[index].as_ref().is_none_or(BindGroup::is_stale) {
+            let view = self.resolve_texture(slot, resources);
+            let group = gpu.new_bind_group()
+                .with_label(&format!("Bindless texture {slot}"))
+                .with_texture_view(&view, self.visibility)
+                .finish(resources)?;
+            self.texture_groups[index] = Some(group);
+        }
+        Ok(self.texture_groups[index].as_ref().unwrap())
+    }
+
+    /// Like `texture_bind_group`, for buffer slots
+    pub fn buffer_bind_group(&mut self, gpu: &Gpu, resources: &mut ResourceManager, slot: u32) -> Result<&BindGroup, BindGroupError> {
+        if self.mode == BindlessMode::Array {
+            return self.table(gpu, resources);
+        }
+
+        let index = slot as usize;
+        if self.buffer_groups.len() <= index {
+            self.buffer_groups.resize_with(index + 1, || None);
+        }
+        if self.buffer_groups[index].as_ref().is_none_or(BindGroup::is_stale) {
+            let buffer = self.resolve_buffer(slot, resources);
+            let view = buffer.view_read(0, buffer.raw.size());
+            let group = gpu.new_bind_group()
+                .with_label(&format!("Bindless buffer {slot}"))
+                .with_buffer(&view, self.visibility)
+                .finish(resources)?;
+            self.buffer_groups[index] = Some(group);
+        }
+        Ok(self.buffer_groups[index].as_ref().unwrap())
+    }
+
+    /// The single table bind group, rebuilt after slots change or a texture or buffer in it was resized or destroyed.
+    /// Only available in `Array` mode
+    pub fn table(&mut self, gpu: &Gpu, resources: &mut ResourceManager) -> Result<&BindGroup, BindGroupError> {
+        if self.mode != BindlessMode::Array {
+            return Err(BindGroupError::MissingFeature {
+                binding: 0,
+                feature: wgpu::Features::TEXTURE_BINDING_ARRAY | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+            });
+        }
+
+        if self.table.as_ref().is_none_or(BindGroup::is_stale) {
+            let views: Vec<TextureView> = (0..self.textures.capacity)
+                .map(|slot| self.resolve_texture(slot, resources))
+                .collect();
+            let views: Vec<&TextureView> = views.iter().collect();
+
+            let buffers: Vec<Buffer> = (0..self.buffers.capacity)
+                .map(|slot| self.resolve_buffer(slot, resources))
+                .collect();
+            let buffer_views: Vec<_> = buffers.iter().map(|buffer| buffer.view_read(0, buffer.raw.size())).collect();
+            let buffer_views: Vec<_> = buffer_views.iter().collect();
+
+            let mut builder = gpu.new_bind_group();
+            builder.with_label("Bindless table");
+            if !views.is_empty() {
+                builder.binding(0).with_texture_view_array(&views, self.visibility);
+            }
+            if !buffer_views.is_empty() {
+                builder.binding(1).with_buffer_array(&buffer_views, self.visibility);
+            }
+            self.table = Some(builder.finish(resources)?);
+        }
+        Ok(self.table.as_ref().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_need_storage_without_uniform() {
+        assert_eq!(check_buffer_usage(wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST), Ok(()));
+        assert!(matches!(check_buffer_usage(wgpu::BufferUsages::UNIFORM), Err(BindlessError::Invalid(_))));
+        assert!(matches!(
+            check_buffer_usage(wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::STORAGE),
+            Err(BindlessError::Invalid(reason)) if reason.contains("UNIFORM")
+        ));
+    }
+
+    #[test]
+    fn freed_slots_are_reused() {
+        let mut slots = Slots::new(4);
+        assert_eq!(slots.insert("a", "texture"), Ok(0));
+        assert_eq!(slots.insert("b", "texture"), Ok(1));
+        assert_eq!(slots.insert("c", "texture"), Ok(2));
+
+        assert!(slots.remove(1));
+        assert!(!slots.remove(1));
+        assert_eq!(slots.get(1), None);
+        assert_eq!(slots.len(), 2);
+
+        assert_eq!(slots.insert("d", "texture"), Ok(1));
+        assert_eq!(slots.get(1), Some(&"d"));
+        assert_eq!(slots.replace(3, "e", "texture"), Err(BindlessError::UnknownSlot { what: "texture", slot: 3 }));
+    }
+
+    #[test]
+    fn insert_fails_at_capacity() {
+        let mut slots = Slots::new(2);
+        assert_eq!(slots.insert(0, "buffer"), Ok(0));
+        assert_eq!(slots.insert(1, "buffer"), Ok(1));
+        assert_eq!(slots.insert(2, "buffer"), Err(BindlessError::Full { what: "buffer", capacity: 2 }));
+
+        // a freed slot makes room again
+        slots.remove(0);
+        assert_eq!(slots.insert(3, "buffer"), Ok(0));
+
+        let mut empty = Slots::new(0);
+        assert_eq!(empty.insert((), "texture"), Err(BindlessError::Full { what: "texture", capacity: 0 }));
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use glam::UVec2;
+use wgpu::util::DeviceExt;
+
+use crate::{gpu::Gpu, texture::{TextureError, TextureView}};
+
+/// A rectangle of texels, `None` in a `BlitDesc` means the whole view
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlitRect {
+    pub origin: UVec2,
+    pub size: UVec2,
+}
+
+/// Where each output channel comes from in a blit
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    R = 0,
+    G = 1,
+    B = 2,
+    A = 3,
+    Zero = 4,
+    One = 5,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlitDesc {
+    pub filter: wgpu::FilterMode,
+    pub src_rect: Option<BlitRect>,
+    pub dst_rect: Option<BlitRect>,
+    pub swizzle: [Channel; 4],
+}
+
+impl Default for BlitDesc {
+    fn default() -> Self {
+        Self {
+            filter: wgpu::FilterMode::Linear,
+            src_rect: None,
+            dst_rect: None,
+            swizzle: [Channel::R, Channel::G, Channel::B, Channel::A],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BlitParams {
+    src_origin: [f32; 2],
+    src_size: [f32; 2],
+    swizzle: [u32; 4],
+    linear: u32,
+    _pad: [u32; 3],
+}
+
+// the params cache is cleared once it holds this many buffers, bind groups that were already recorded keep theirs alive
+const MAX_CACHED_PARAMS: usize = 256;
+
+/// Shared state for `Gpu::blit`, created the first time a blit needs a render pass
+pub(crate) struct Blitter {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    module: wgpu::ShaderModule,
+    // one pipeline per destination format
+    pipelines: Mutex<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
+    // one uniform buffer per distinct set of params, written once so blits recorded before a submit can't overwrite each other
+    params: Mutex<HashMap<[u32; 12], wgpu::Buffer>>,
+}
+
+impl Blitter {
+    fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            layout,
+            pipeline_layout,
+            module: device.create_shader_module(wgpu::include_wgsl!("shaders/blit.wgsl")),
+            pipelines: Mutex::new(HashMap::new()),
+            params: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn params(&self, device: &wgpu::Device, params: &BlitParams) -> wgpu::Buffer {
+        let mut cache = self.params.lock().unwrap();
+        let key: [u32; 12] = bytemuck::cast(*params);
+        if cache.len() >= MAX_CACHED_PARAMS && !cache.contains_key(&key) {
+            cache.clear();
+        }
+        cache.entry(key).or_insert_with(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Blit params"),
+                contents: bytemuck::bytes_of(params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        }).clone()
+    }
+
+    fn pipeline(&self, device: &wgpu::Device, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        self.pipelines.lock().unwrap().entry(format).or_insert_with(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Blit"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.module,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        }).clone()
+    }
+}
+
+fn check_rect(rect: &BlitRect, size: UVec2, name: &str) -> Result<(), TextureError> {
+    let end = rect.origin.x.checked_add(rect.size.x).zip(rect.origin.y.checked_add(rect.size.y));
+    match end {
+        Some((x, y)) if x <= size.x && y <= size.y => Ok(()),
+        _ => Err(TextureError::Other(format!(
+            "blit {name} rect {:?}+{:?} is outside the {}x{} view", rect.origin, rect.size, size.x, size.y
+        ))),
+    }
+}
+
+/// The mip and array layer ranges a view covers
+fn subresources(view: &TextureView) -> (Range<u32>, Range<u32>) {
+    let texture = view.raw.texture();
+    let mips = view.mip_level_count.unwrap_or(texture.mip_level_count().saturating_sub(view.base_mip_level));
+    let layers = view.array_layer_count.unwrap_or(texture.depth_or_array_layers().saturating_sub(view.base_array_layer));
+    (
+        view.base_mip_level..view.base_mip_level + mips,
+        view.base_array_layer..view.base_array_layer + layers,
+    )
+}
+
+fn view_covers((mips, layers): &(Range<u32>, Range<u32>), mip: u32, layer: u32) -> bool {
+    mips.contains(&mip) && layers.contains(&layer)
+}
+
+fn view_size(view: &TextureView) -> UVec2 {
+    let texture = view.raw.texture();
+    UVec2::new(
+        (texture.width() >> view.base_mip_level).max(1),
+        (texture.height() >> view.base_mip_level).max(1),
+    )
+}
+
+impl Gpu {
+    /// Copy `src_rect` of `src` into `dst_rect` of `dst`, converting between formats and sizes as needed
+    ///
+    /// Records and submits its own command buffer, see `encode_blit` to record into an existing encoder
+    pub fn blit(&self, src: &TextureView, dst: &TextureView, filter: wgpu::FilterMode, src_rect: Option<BlitRect>, dst_rect: Option<BlitRect>) -> Result<(), TextureError> {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Blit") });
+        let desc = BlitDesc {
+            filter,
+            src_rect,
+            dst_rect,
+            ..Default::default()
+        };
+        self.encode_blit(&mut encoder, src, dst, &desc)?;
+        self.queue.submit([encoder.finish()]);
+        Ok(())
+    }
+
+    /// Record a blit into `encoder`
+    ///
+    /// Uses `copy_texture_to_texture` when the formats and rect sizes match, there is no swizzle and the textures
+    /// have copy usages, and a fullscreen draw into the destination viewport otherwise. Drawing needs a float
+    /// sampleable, single sampled 2D source and a renderable, single sampled destination
+    ///
+    /// Returns an error instead of recording anything when the rects fall outside their views or the source
+    /// view includes the destination mip and layer
+    pub fn encode_blit(&self, encoder: &mut wgpu::CommandEncoder, src: &TextureView, dst: &TextureView, desc: &BlitDesc) -> Result<(), TextureError> {
+        let (src_size, dst_size) = (view_size(src), view_size(dst));
+        let src_rect = desc.src_rect.unwrap_or(BlitRect { origin: UVec2::ZERO, size: src_size });
+        let dst_rect = desc.dst_rect.unwrap_or(BlitRect { origin: UVec2::ZERO, size: dst_size });
+        let (src_texture, dst_texture) = (src.raw.texture(), dst.raw.texture());
+
+        check_rect(&src_rect, src_size, "source")?;
+        check_rect(&dst_rect, dst_size, "destination")?;
+        if src_rect.size.cmpeq(UVec2::ZERO).any() || dst_rect.size.cmpeq(UVec2::ZERO).any() {
+            return Ok(());
+        }
+
+        // the source view is bound whole, so it can't include the mip and layer being rendered to
+        let same_texture = src_texture == dst_texture;
+        if same_texture && view_covers(&subresources(src), dst.base_mip_level, dst.base_array_layer) {
+            return Err(TextureError::Other(format!(
+                "blit source view includes the destination mip {} layer {}, view a single mip of the source instead",
+                dst.base_mip_level, dst.base_array_layer
+            )));
+        }
+
+        // multisampled copies have to cover the whole texture
+        let whole = |rect: &BlitRect, texture: &wgpu::Texture| rect.origin == UVec2::ZERO && rect.size == UVec2::new(texture.width(), texture.height());
+        let identity = desc.swizzle == BlitDesc::default().swizzle;
+        let can_copy = identity
+            && !same_texture
+            && src.format == dst.format
+            && src_rect.size == dst_rect.size
+            && src_texture.sample_count() == dst_texture.sample_count()
+            && (src_texture.sample_count() == 1 || (whole(&src_rect, src_texture) && whole(&dst_rect, dst_texture)))
+            && src_texture.usage().contains(wgpu::TextureUsages::COPY_SRC)
+            && dst_texture.usage().contains(wgpu::TextureUsages::COPY_DST);
+
+        if can_copy {
+            encoder.copy_texture_to_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: src_texture,
+                    mip_level: src.base_mip_level,
+                    origin: wgpu::Origin3d { x: src_rect.origin.x, y: src_rect.origin.y, z: src.base_array_layer },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::TexelCopyTextureInfo {
+                    texture: dst_texture,
+                    mip_level: dst.base_mip_level,
+                    origin: wgpu::Origin3d { x: dst_rect.origin.x, y: dst_rect.origin.y, z: dst.base_array_layer },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d { width: src_rect.size.x, height: src_rect.size.y, depth_or_array_layers: 1 },
+            );
+            return Ok(());
+        }
+
+        if !matches!(src.format.sample_type(None, None), Some(wgpu::TextureSampleType::Float { .. })) {
+            return Err(TextureError::Other(format!("blit source must be a float sampleable format, got {:?}", src.format)));
+        }
+        if src.dimension != wgpu::TextureViewDimension::D2 {
+            return Err(TextureError::Other(format!("blit source must be a D2 view, got {:?}", src.dimension)));
+        }
+        if src_texture.sample_count() > 1 {
+            return Err(TextureError::Other("blit source must be single sampled when formats or sizes differ, resolve it first".into()));
+        }
+        if !dst_texture.usage().contains(wgpu::TextureUsages::RENDER_ATTACHMENT) {
+            return Err(TextureError::Other("blit destination needs RENDER_ATTACHMENT usage when formats or sizes differ".into()));
+        }
+        if dst_texture.sample_count() > 1 {
+            return Err(TextureError::Other("blit destination must be single sampled when formats or sizes differ".into()));
+        }
+
+        let params = BlitParams {
+            src_origin: src_rect.origin.as_vec2().to_array(),
+            src_size: src_rect.size.as_vec2().to_array(),
+            swizzle: desc.swizzle.map(|c| c as u32),
+            linear: (desc.filter == wgpu::FilterMode::Linear) as u32,
+            _pad: [0; 3],
+        };
+        let blitter = self.blitter.get_or_init(|| Blitter::new(&self.device));
+        let params = blitter.params(&self.device, &params);
+        let pipeline = blitter.pipeline(&self.device, dst.format);
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit"),
+            layout: &blitter.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&src.raw),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut attachment = dst.attachment();
+        attachment.ops.load = wgpu::LoadOp::Load;
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit"),
+            color_attachments: &[Some(attachment)],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_viewport(dst_rect.origin.x as f32, dst_rect.origin.y as f32, dst_rect.size.x as f32, dst_rect.size.y as f32, 0.0, 1.0);
+        pass.set_scissor_rect(dst_rect.origin.x, dst_rect.origin.y, dst_rect.size.x, dst_rect.size.y);
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_must_fit_the_view() {
+        let size = UVec2::new(64, 32);
+        let rect = |x, y, w, h| BlitRect { origin: UVec2::new(x, y), size: UVec2::new(w, h) };
+        assert!(check_rect(&rect(0, 0, 64, 32), size, "source").is_ok());
+        assert!(check_rect(&rect(60, 30, 4, 2), size, "source").is_ok());
+        assert!(check_rect(&rect(64, 0, 0, 0), size, "source").is_ok());
+
+        assert!(check_rect(&rect(61, 0, 4, 1), size, "source").is_err());
+        assert!(check_rect(&rect(0, 0, 1, 33), size, "source").is_err());
+        // origin + size wraps around in u32
+        assert!(check_rect(&rect(u32::MAX, 0, 2, 1), size, "source").is_err());
+        assert!(check_rect(&rect(0, 1, 1, u32::MAX), size, "source").is_err());
+
+        let Err(TextureError::Other(message)) = check_rect(&rect(8, 8, 64, 4), size, "destination") else { panic!() };
+        assert_eq!(message, "blit destination rect UVec2(8, 8)+UVec2(64, 4) is outside the 64x32 view");
+    }
+
+    #[test]
+    fn source_views_can_cover_the_destination() {
+        // a full chain view of 5 mips and 2 layers
+        let full = (0..5, 0..2);
+        assert!(view_covers(&full, 2, 1));
+        assert!(!view_covers(&full, 5, 0));
+
+        // a single mip view only conflicts with that mip
+        let mip = (1..2, 0..1);
+        assert!(view_covers(&mip, 1, 0));
+        assert!(!view_covers(&mip, 2, 0));
+        assert!(!view_covers(&mip, 1, 1));
+    }
+}
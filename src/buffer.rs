@@ -1,5 +1,7 @@
 use std::num::NonZero;
 
+use crate::resource::Generation;
+
 
 
 impl std::ops::Deref for Buffer {
@@ -10,8 +12,11 @@ impl std::ops::Deref for Buffer {
     }
 }
 
+#[derive(Clone)]
 pub struct Buffer {
     pub raw: wgpu::Buffer,
+    /// Bumped by `Gpu::grow_buffer`
+    pub generation: Generation,
 }
 
 impl Buffer {
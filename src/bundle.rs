@@ -0,0 +1,42 @@
+use std::borrow::Cow;
+
+use crate::preprocess::PreprocessError;
+
+pub use hb_gpu_macros::{include_wgsl_bundle, include_wgsl_structs};
+
+/// A WGSL file flattened and validated at build time by `include_wgsl_bundle!`
+///
+/// The embedded code works everywhere, including wasm without filesystem access. Debug builds on native can read
+/// the files again instead, see `source` and `ShaderWatcher::load_bundle`
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderBundle {
+    /// Absolute path of the root file on the machine that built it
+    pub path: &'static str,
+    /// The flattened source as it was at build time
+    pub code: &'static str,
+    /// Every file that went into the bundle, including the root
+    pub files: &'static [&'static str],
+}
+
+impl ShaderBundle {
+    /// The embedded code, ready for `create_shader_module`
+    pub fn descriptor(&self) -> wgpu::ShaderModuleDescriptor<'static> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some(self.path),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(self.code)),
+        }
+    }
+
+    /// The current source: preprocessed from disk in native debug builds so edits show up without rebuilding, the
+    /// embedded code otherwise. Fails when the files can't be read or preprocessed anymore, `code` is still there
+    /// to fall back on
+    pub fn source(&self) -> Result<Cow<'static, str>, PreprocessError> {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            let shader = crate::preprocess::preprocess(self.path, |file| std::fs::read_to_string(file).map_err(|e| e.to_string()))?;
+            Ok(Cow::Owned(shader.code))
+        }
+        #[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
+        Ok(Cow::Borrowed(self.code))
+    }
+}
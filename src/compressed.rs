@@ -0,0 +1,764 @@
+use std::io::Read;
+
+use wgpu::util::DeviceExt;
+
+use crate::{gpu::Gpu, texture::{Texture, TextureError}};
+
+/// Texture data decoded from a KTX2 or DDS file, laid out exactly as the container stores it
+pub struct ContainerImage {
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    dimension: wgpu::TextureDimension,
+    view_dimension: wgpu::TextureViewDimension,
+    mip_level_count: u32,
+    order: wgpu::util::TextureDataOrder,
+    data: Vec<u8>,
+    /// Set for UASTC data, which `format` describes as ASTC 4x4 until it is transcoded for the device
+    #[cfg(feature = "basis")]
+    uastc: Option<basis::Uastc>,
+}
+
+fn view_dimension_for(layers: u32, faces: u32, depth: u32) -> wgpu::TextureViewDimension {
+    match (layers, faces) {
+        _ if depth > 1 => wgpu::TextureViewDimension::D3,
+        (1, 6) => wgpu::TextureViewDimension::Cube,
+        (_, 6) => wgpu::TextureViewDimension::CubeArray,
+        (1, _) => wgpu::TextureViewDimension::D2,
+        _ => wgpu::TextureViewDimension::D2Array,
+    }
+}
+
+fn astc_block(index: u32) -> wgpu::AstcBlock {
+    use wgpu::AstcBlock::*;
+    [B4x4, B5x4, B5x5, B6x5, B6x6, B8x5, B8x6, B8x8, B10x5, B10x6, B10x8, B10x10, B12x10, B12x12][index as usize]
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as W;
+
+    let vk = format.value();
+    if (K::ASTC_4x4_UNORM_BLOCK.value()..=K::ASTC_12x12_SRGB_BLOCK.value()).contains(&vk) {
+        let index = vk - K::ASTC_4x4_UNORM_BLOCK.value();
+        let channel = if index.is_multiple_of(2) { wgpu::AstcChannel::Unorm } else { wgpu::AstcChannel::UnormSrgb };
+        return Some(W::Astc { block: astc_block(index / 2), channel });
+    }
+    if (K::ASTC_4x4_SFLOAT_BLOCK.value()..=K::ASTC_12x12_SFLOAT_BLOCK.value()).contains(&vk) {
+        let index = vk - K::ASTC_4x4_SFLOAT_BLOCK.value();
+        return Some(W::Astc { block: astc_block(index), channel: wgpu::AstcChannel::Hdr });
+    }
+
+    Some(match format {
+        K::R8_UNORM                     => W::R8Unorm,
+        K::R8G8_UNORM                   => W::Rg8Unorm,
+        K::R8G8B8A8_UNORM               => W::Rgba8Unorm,
+        K::R8G8B8A8_SRGB                => W::Rgba8UnormSrgb,
+        K::B8G8R8A8_UNORM               => W::Bgra8Unorm,
+        K::B8G8R8A8_SRGB                => W::Bgra8UnormSrgb,
+        K::A2B10G10R10_UNORM_PACK32     => W::Rgb10a2Unorm,
+        K::B10G11R11_UFLOAT_PACK32      => W::Rg11b10Ufloat,
+        K::E5B9G9R9_UFLOAT_PACK32       => W::Rgb9e5Ufloat,
+        K::R16_SFLOAT                   => W::R16Float,
+        K::R16G16_SFLOAT                => W::Rg16Float,
+        K::R16G16B16A16_SFLOAT          => W::Rgba16Float,
+        K::R32_SFLOAT                   => W::R32Float,
+        K::R32G32_SFLOAT                => W::Rg32Float,
+        K::R32G32B32A32_SFLOAT          => W::Rgba32Float,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => W::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK  | K::BC1_RGBA_SRGB_BLOCK  => W::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK              => W::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK               => W::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK              => W::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK               => W::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK              => W::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK              => W::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK              => W::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK              => W::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK            => W::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK            => W::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK              => W::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK               => W::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK      => W::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK       => W::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK    => W::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK     => W::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK    => W::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK     => W::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK          => W::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK          => W::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK       => W::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK       => W::EacRg11Snorm,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as W;
+
+    Some(match format {
+        D::R8_UNorm                     => W::R8Unorm,
+        D::R8G8_UNorm                   => W::Rg8Unorm,
+        D::R8G8B8A8_UNorm               => W::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB          => W::Rgba8UnormSrgb,
+        D::B8G8R8A8_UNorm               => W::Bgra8Unorm,
+        D::B8G8R8A8_UNorm_sRGB          => W::Bgra8UnormSrgb,
+        D::R10G10B10A2_UNorm            => W::Rgb10a2Unorm,
+        D::R11G11B10_Float              => W::Rg11b10Ufloat,
+        D::R9G9B9E5_SharedExp           => W::Rgb9e5Ufloat,
+        D::R16_Float                    => W::R16Float,
+        D::R16G16_Float                 => W::Rg16Float,
+        D::R16G16B16A16_Float           => W::Rgba16Float,
+        D::R32_Float                    => W::R32Float,
+        D::R32G32_Float                 => W::Rg32Float,
+        D::R32G32B32A32_Float           => W::Rgba32Float,
+        D::BC1_UNorm                    => W::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB               => W::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm                    => W::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB               => W::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm                    => W::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB               => W::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm                    => W::Bc4RUnorm,
+        D::BC4_SNorm                    => W::Bc4RSnorm,
+        D::BC5_UNorm                    => W::Bc5RgUnorm,
+        D::BC5_SNorm                    => W::Bc5RgSnorm,
+        D::BC6H_UF16                    => W::Bc6hRgbUfloat,
+        D::BC6H_SF16                    => W::Bc6hRgbFloat,
+        D::BC7_UNorm                    => W::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB               => W::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::D3DFormat as D;
+    use wgpu::TextureFormat as W;
+
+    Some(match format {
+        D::DXT1     => W::Bc1RgbaUnorm,
+        D::DXT2 | D::DXT3 => W::Bc2RgbaUnorm,
+        D::DXT4 | D::DXT5 => W::Bc3RgbaUnorm,
+        D::A8B8G8R8 => W::Rgba8Unorm,
+        D::A8R8G8B8 => W::Bgra8Unorm,
+        D::L8       => W::R8Unorm,
+        _ => return None,
+    })
+}
+
+/// KTX2 and DDS store a height or depth of 0 for 1D and 2D images, which is read as 1, but the width and the
+/// resulting size can't be empty
+fn check_size(size: wgpu::Extent3d, container: &str) -> Result<wgpu::Extent3d, TextureError> {
+    if size.width == 0 || size.height == 0 || size.depth_or_array_layers == 0 {
+        return Err(TextureError::Other(format!(
+            "{container} file describes an empty {}x{}x{} texture", size.width, size.height, size.depth_or_array_layers
+        )));
+    }
+    Ok(size)
+}
+
+impl ContainerImage {
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, TextureError> {
+        let reader = ktx2::Reader::new(bytes)
+            .map_err(|e| TextureError::Other(format!("invalid KTX2 file: {e:?}")))?;
+        let header = reader.header();
+
+        #[cfg(feature = "basis")]
+        let mut uastc = None;
+        let format = match header.format {
+            Some(format) => ktx2_format(format)
+                .ok_or_else(|| TextureError::Other(format!("unsupported KTX2 format {format:?}")))?,
+            // VK_FORMAT_UNDEFINED is how Basis Universal (ETC1S and UASTC) payloads are marked
+            None => {
+                if header.supercompression_scheme == Some(ktx2::SupercompressionScheme::BasisLZ) {
+                    return Err(TextureError::Other("KTX2 file contains ETC1S data, only UASTC Basis Universal files are supported".into()));
+                }
+                let dfd = reader.dfd_blocks().next()
+                    .and_then(|block| ktx2::DfdBlockBasic::parse(block.data).ok())
+                    .ok_or_else(|| TextureError::Other("KTX2 file without a format has no data format descriptor".into()))?;
+                if dfd.header.color_model != Some(ktx2::ColorModel::UASTC) {
+                    return Err(TextureError::Other(format!("unsupported KTX2 color model {:?}", dfd.header.color_model)));
+                }
+                #[cfg(not(feature = "basis"))]
+                return Err(TextureError::Other("KTX2 file contains UASTC data, enable the `basis` feature to transcode it".into()));
+                #[cfg(feature = "basis")]
+                {
+                    // UASTC channel types: 0 is RGB and 4 is RRR, the rest carry a second channel in alpha
+                    let has_alpha = dfd.sample_information().next().is_some_and(|sample| !matches!(sample.channel_type, 0 | 4));
+                    uastc = Some(basis::Uastc { has_alpha });
+                    let channel = match dfd.header.transfer_function {
+                        Some(ktx2::TransferFunction::SRGB) => wgpu::AstcChannel::UnormSrgb,
+                        _ => wgpu::AstcChannel::Unorm,
+                    };
+                    wgpu::TextureFormat::Astc { block: wgpu::AstcBlock::B4x4, channel }
+                }
+            },
+        };
+
+        let mut data = Vec::new();
+        for level in reader.levels() {
+            match header.supercompression_scheme {
+                None => data.extend_from_slice(level.data),
+                Some(ktx2::SupercompressionScheme::Zstandard) => {
+                    let mut decoder = ruzstd::decoding::StreamingDecoder::new(level.data)
+                        .map_err(|e| TextureError::Other(format!("invalid zstd stream in KTX2 file: {e}")))?;
+                    decoder.read_to_end(&mut data)?;
+                },
+                Some(scheme) => return Err(TextureError::Other(format!("unsupported KTX2 supercompression scheme {scheme:?}"))),
+            }
+        }
+
+        let layers = header.layer_count.max(1);
+        let faces = header.face_count.max(1);
+        let depth = header.pixel_depth.max(1);
+        let size = check_size(wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: if depth > 1 { depth } else { layers * faces },
+        }, "KTX2")?;
+        Ok(Self {
+            format,
+            size,
+            dimension: if depth > 1 { wgpu::TextureDimension::D3 } else { wgpu::TextureDimension::D2 },
+            view_dimension: view_dimension_for(layers, faces, depth),
+            mip_level_count: header.level_count.max(1),
+            order: wgpu::util::TextureDataOrder::MipMajor,
+            data,
+            #[cfg(feature = "basis")]
+            uastc,
+        })
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, TextureError> {
+        let dds = ddsfile::Dds::read(bytes)
+            .map_err(|e| TextureError::Other(format!("invalid DDS file: {e}")))?;
+
+        let format = dds.get_dxgi_format().and_then(dxgi_format)
+            .or_else(|| dds.get_d3d_format().and_then(d3d_format))
+            .ok_or_else(|| TextureError::Other(format!(
+                "unsupported DDS format {:?}", dds.get_dxgi_format().map(|f| format!("{f:?}")).or(dds.get_d3d_format().map(|f| format!("{f:?}")))
+            )))?;
+
+        let is_cube = match &dds.header10 {
+            Some(h10) => h10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE),
+            None => dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP),
+        };
+        let faces = if is_cube { 6 } else { 1 };
+        // DX10 headers count whole cubes, legacy headers already report 6 layers for a cubemap
+        let layers = match &dds.header10 {
+            Some(_) => dds.get_num_array_layers().max(1),
+            None => dds.get_num_array_layers().max(1) / faces,
+        };
+        let depth = dds.get_depth().max(1);
+
+        let size = check_size(wgpu::Extent3d {
+            width: dds.get_width(),
+            height: dds.get_height().max(1),
+            depth_or_array_layers: if depth > 1 { depth } else { layers * faces },
+        }, "DDS")?;
+        Ok(Self {
+            format,
+            size,
+            dimension: if depth > 1 { wgpu::TextureDimension::D3 } else { wgpu::TextureDimension::D2 },
+            view_dimension: view_dimension_for(layers, faces, depth),
+            mip_level_count: dds.get_num_mipmap_levels().max(1),
+            order: wgpu::util::TextureDataOrder::LayerMajor,
+            data: dds.data,
+            #[cfg(feature = "basis")]
+            uastc: None,
+        })
+    }
+
+    /// (width, height, depth) of every image in the order the container stores them
+    fn images(&self) -> Vec<(u32, u32, u32)> {
+        let layers = if self.dimension == wgpu::TextureDimension::D3 { 1 } else { self.size.depth_or_array_layers };
+        let mip_size = |mip: u32| {
+            let depth = if layers == 1 { (self.size.depth_or_array_layers >> mip).max(1) } else { 1 };
+            ((self.size.width >> mip).max(1), (self.size.height >> mip).max(1), depth)
+        };
+        match self.order {
+            wgpu::util::TextureDataOrder::LayerMajor => (0..layers)
+                .flat_map(|_| (0..self.mip_level_count).map(mip_size))
+                .collect(),
+            _ => (0..self.mip_level_count)
+                .flat_map(|mip| (0..layers).map(move |_| mip_size(mip)))
+                .collect(),
+        }
+    }
+}
+
+impl Gpu {
+    /// Load a KTX2 file (optionally Zstd supercompressed) with all of its mips, layers and faces
+    ///
+    /// Block compressed formats the device can't sample are decompressed on the CPU, except BC6H and HDR ASTC.
+    /// UASTC Basis Universal data is transcoded to ASTC, BC7 or ETC2 with the `basis` feature, ETC1S isn't supported
+    pub fn new_texture_from_ktx2(&self, bytes: &[u8]) -> Result<Texture, TextureError> {
+        let image = ContainerImage::from_ktx2(bytes)?;
+        self.new_texture_from_container(image)
+    }
+
+    /// Load a DDS file with all of its mips, layers and faces
+    ///
+    /// Block compressed formats the device can't sample are decompressed on the CPU, except BC6H
+    pub fn new_texture_from_dds(&self, bytes: &[u8]) -> Result<Texture, TextureError> {
+        let image = ContainerImage::from_dds(bytes)?;
+        self.new_texture_from_container(image)
+    }
+
+    /// Upload a parsed container image. UASTC data is transcoded to the best format the device supports. If the device
+    /// lacks the feature for a block compressed format, it is decompressed to RGBA8 on the CPU where a decoder exists
+    pub(crate) fn new_texture_from_container(&self, mut image: ContainerImage) -> Result<Texture, TextureError> {
+        #[cfg(feature = "basis")]
+        if let Some(uastc) = image.uastc.take() {
+            (image.format, image.data) = basis::transcode(&image, uastc, self.device.features())?;
+        }
+
+        let required = image.format.required_features();
+        if !self.device.features().contains(required) {
+            let Some(fallback) = decompress::decompressed_format(image.format) else {
+                return Err(TextureError::Other(format!(
+                    "{:?} needs device features {required:?}, and there is no CPU fallback for it", image.format
+                )));
+            };
+            image.data = decompress::decompress_all(&image)?;
+            image.format = fallback;
+        }
+
+        let desc = wgpu::TextureDescriptor {
+            label: None,
+            size: image.size,
+            mip_level_count: image.mip_level_count,
+            sample_count: 1,
+            dimension: image.dimension,
+            format: image.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+
+        let mut tex = Texture {
+            raw: self.device.create_texture_with_data(&self.queue, &desc, image.order, &image.data),
+            label: None,
+            dim: image.view_dimension,
+            views: Vec::new(),
+            generation: Default::default(),
+        };
+        tex.label = Some(tex.default_label());
+        tex.new_view();
+        Ok(tex)
+    }
+}
+
+/// CPU decoders for block compressed formats the device can't sample. BC1-BC5 are decoded here, BC7, ETC2, EAC and
+/// LDR ASTC through `texture2ddecoder`. Missing BC support is common on mobile and web, missing ETC2 and ASTC on desktop
+mod decompress {
+    use super::ContainerImage;
+    use crate::texture::TextureError;
+
+    pub fn decompressed_format(format: wgpu::TextureFormat) -> Option<wgpu::TextureFormat> {
+        use wgpu::TextureFormat as W;
+        match format {
+            W::Bc1RgbaUnorm | W::Bc2RgbaUnorm | W::Bc3RgbaUnorm | W::Bc4RUnorm | W::Bc5RgUnorm | W::Bc7RgbaUnorm
+            | W::Etc2Rgb8Unorm | W::Etc2Rgb8A1Unorm | W::Etc2Rgba8Unorm | W::EacR11Unorm | W::EacRg11Unorm
+            | W::Astc { channel: wgpu::AstcChannel::Unorm, .. } => Some(W::Rgba8Unorm),
+            W::Bc1RgbaUnormSrgb | W::Bc2RgbaUnormSrgb | W::Bc3RgbaUnormSrgb | W::Bc7RgbaUnormSrgb
+            | W::Etc2Rgb8UnormSrgb | W::Etc2Rgb8A1UnormSrgb | W::Etc2Rgba8UnormSrgb
+            | W::Astc { channel: wgpu::AstcChannel::UnormSrgb, .. } => Some(W::Rgba8UnormSrgb),
+            _ => None,
+        }
+    }
+
+    /// Decompress every mip and layer of `image`, keeping its data order
+    pub fn decompress_all(image: &ContainerImage) -> Result<Vec<u8>, TextureError> {
+        let block_size = image.format.block_copy_size(None).unwrap_or(16) as usize;
+        let (block_width, block_height) = image.format.block_dimensions();
+
+        let mut out = Vec::new();
+        let mut offset = 0;
+        for (width, height, depth) in image.images() {
+            let blocks_x = width.div_ceil(block_width) as usize;
+            let blocks_y = height.div_ceil(block_height) as usize;
+            let len = blocks_x * blocks_y * block_size * depth as usize;
+            let Some(blocks) = image.data.get(offset..offset + len) else {
+                return Err(TextureError::Other("compressed texture data is shorter than its header describes".into()));
+            };
+            offset += len;
+
+            for slice in blocks.chunks(blocks_x * blocks_y * block_size) {
+                out.extend(decompress_image(image.format, slice, width as usize, height as usize, blocks_x));
+            }
+        }
+        Ok(out)
+    }
+
+    fn decompress_image(format: wgpu::TextureFormat, blocks: &[u8], width: usize, height: usize, blocks_x: usize) -> Vec<u8> {
+        let (block_width, block_height) = format.block_dimensions();
+        let (block_width, block_height) = (block_width as usize, block_height as usize);
+
+        let mut out = vec![0u8; width * height * 4];
+        let block_size = blocks.len() / (blocks_x * height.div_ceil(block_height));
+        // ASTC blocks go up to 12x12
+        let mut texels = [[0u8; 4]; 144];
+        for (i, block) in blocks.chunks_exact(block_size).enumerate() {
+            decode_block(format, block, &mut texels[..block_width * block_height]);
+
+            let (bx, by) = (i % blocks_x * block_width, i / blocks_x * block_height);
+            for (t, texel) in texels[..block_width * block_height].iter().enumerate() {
+                let (x, y) = (bx + t % block_width, by + t / block_width);
+                if x < width && y < height {
+                    let p = (y * width + x) * 4;
+                    out[p..p + 4].copy_from_slice(texel);
+                }
+            }
+        }
+        out
+    }
+
+    /// Decode one block into RGBA8 texels in row order
+    pub(super) fn decode_block(format: wgpu::TextureFormat, block: &[u8], texels: &mut [[u8; 4]]) {
+        use wgpu::TextureFormat as W;
+
+        match format {
+            W::Bc1RgbaUnorm | W::Bc1RgbaUnormSrgb => color_block(block, texels, true),
+            W::Bc2RgbaUnorm | W::Bc2RgbaUnormSrgb => {
+                color_block(&block[8..], texels, false);
+                for (t, texel) in texels.iter_mut().enumerate() {
+                    let nibble = (block[t / 2] >> ((t % 2) * 4)) & 0xF;
+                    texel[3] = nibble * 17;
+                }
+            },
+            W::Bc3RgbaUnorm | W::Bc3RgbaUnormSrgb => {
+                color_block(&block[8..], texels, false);
+                channel_block(&block[..8], texels, 3);
+            },
+            W::Bc4RUnorm => {
+                channel_block(block, texels, 0);
+                texels.iter_mut().for_each(|t| t[3] = 255);
+            },
+            W::Bc5RgUnorm => {
+                channel_block(&block[..8], texels, 0);
+                channel_block(&block[8..], texels, 1);
+                texels.iter_mut().for_each(|t| t[3] = 255);
+            },
+            _ => {
+                let mut decoded = [0u32; 144];
+                match format {
+                    W::Bc7RgbaUnorm | W::Bc7RgbaUnormSrgb => texture2ddecoder::decode_bc7_block(block, &mut decoded),
+                    W::Etc2Rgb8Unorm | W::Etc2Rgb8UnormSrgb => texture2ddecoder::decode_etc2_rgb_block(block, &mut decoded),
+                    W::Etc2Rgb8A1Unorm | W::Etc2Rgb8A1UnormSrgb => texture2ddecoder::decode_etc2_rgba1_block(block, &mut decoded),
+                    W::Etc2Rgba8Unorm | W::Etc2Rgba8UnormSrgb => texture2ddecoder::decode_etc2_rgba8_block(block, &mut decoded),
+                    W::EacR11Unorm => texture2ddecoder::decode_eacr_block(block, &mut decoded),
+                    W::EacRg11Unorm => texture2ddecoder::decode_eacrg_block(block, &mut decoded),
+                    W::Astc { .. } => {
+                        let (width, height) = format.block_dimensions();
+                        texture2ddecoder::decode_astc_block(block, width as usize, height as usize, &mut decoded);
+                    },
+                    _ => unreachable!("no CPU decoder for {format:?}"),
+                }
+                // texture2ddecoder packs texels as little endian BGRA
+                for (texel, bgra) in texels.iter_mut().zip(decoded) {
+                    let [b, g, r, a] = bgra.to_le_bytes();
+                    *texel = [r, g, b, a];
+                }
+            },
+        }
+    }
+
+    fn rgb565(c: u16) -> [u8; 3] {
+        let r = ((c >> 11) & 0x1F) as u32;
+        let g = ((c >> 5) & 0x3F) as u32;
+        let b = (c & 0x1F) as u32;
+        [(r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8]
+    }
+
+    /// BC1 style color endpoints and 2 bit indices. `allow_alpha` enables BC1's 3 color + transparent mode
+    fn color_block(block: &[u8], texels: &mut [[u8; 4]], allow_alpha: bool) {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let (e0, e1) = (rgb565(c0), rgb565(c1));
+
+        let lerp = |a: u8, b: u8, wa: u32, wb: u32| ((a as u32 * wa + b as u32 * wb) / (wa + wb)) as u8;
+        let mut palette = [[0u8; 4]; 4];
+        palette[0] = [e0[0], e0[1], e0[2], 255];
+        palette[1] = [e1[0], e1[1], e1[2], 255];
+        if c0 > c1 || !allow_alpha {
+            palette[2] = [lerp(e0[0], e1[0], 2, 1), lerp(e0[1], e1[1], 2, 1), lerp(e0[2], e1[2], 2, 1), 255];
+            palette[3] = [lerp(e0[0], e1[0], 1, 2), lerp(e0[1], e1[1], 1, 2), lerp(e0[2], e1[2], 1, 2), 255];
+        } else {
+            palette[2] = [lerp(e0[0], e1[0], 1, 1), lerp(e0[1], e1[1], 1, 1), lerp(e0[2], e1[2], 1, 1), 255];
+            palette[3] = [0, 0, 0, 0];
+        }
+
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        for (t, texel) in texels.iter_mut().enumerate() {
+            *texel = palette[((indices >> (t * 2)) & 0x3) as usize];
+        }
+    }
+
+    /// BC4 style single channel block, written into `channel` of each texel
+    fn channel_block(block: &[u8], texels: &mut [[u8; 4]], channel: usize) {
+        let (a0, a1) = (block[0] as u32, block[1] as u32);
+        let mut palette = [0u8; 8];
+        palette[0] = a0 as u8;
+        palette[1] = a1 as u8;
+        if a0 > a1 {
+            for i in 1..7 {
+                palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+            }
+        } else {
+            for i in 1..5 {
+                palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+            }
+            palette[6] = 0;
+            palette[7] = 255;
+        }
+
+        let mut bits = [0u8; 8];
+        bits[..6].copy_from_slice(&block[2..8]);
+        let indices = u64::from_le_bytes(bits);
+        for (t, texel) in texels.iter_mut().enumerate() {
+            texel[channel] = palette[((indices >> (t * 3)) & 0x7) as usize];
+        }
+    }
+}
+
+/// UASTC transcoding with the Basis Universal transcoder
+#[cfg(feature = "basis")]
+mod basis {
+    use basis_universal::{DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat};
+
+    use super::ContainerImage;
+    use crate::texture::TextureError;
+
+    #[derive(Clone, Copy)]
+    pub struct Uastc {
+        pub has_alpha: bool,
+    }
+
+    /// The best format the device can sample, UASTC maps almost directly onto ASTC 4x4 and BC7.
+    /// Devices without any of them get ASTC decoded on the CPU, the transcoder's RGBA32 output overruns its buffer
+    fn target(features: wgpu::Features, srgb: bool) -> (TranscoderBlockFormat, wgpu::TextureFormat) {
+        use wgpu::TextureFormat as W;
+
+        let has_bc = features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+        let has_etc2 = features.contains(wgpu::Features::TEXTURE_COMPRESSION_ETC2);
+        if has_bc && !features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC) {
+            (TranscoderBlockFormat::BC7, if srgb { W::Bc7RgbaUnormSrgb } else { W::Bc7RgbaUnorm })
+        } else if has_etc2 && !features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC) {
+            (TranscoderBlockFormat::ETC2_RGBA, if srgb { W::Etc2Rgba8UnormSrgb } else { W::Etc2Rgba8Unorm })
+        } else {
+            let channel = if srgb { wgpu::AstcChannel::UnormSrgb } else { wgpu::AstcChannel::Unorm };
+            (TranscoderBlockFormat::ASTC_4x4, W::Astc { block: wgpu::AstcBlock::B4x4, channel })
+        }
+    }
+
+    /// Transcode every mip and layer of `image`, keeping its data order
+    pub fn transcode(image: &ContainerImage, uastc: Uastc, features: wgpu::Features) -> Result<(wgpu::TextureFormat, Vec<u8>), TextureError> {
+        let (block_format, format) = target(features, image.format.is_srgb());
+        let transcoder = LowLevelUastcTranscoder::new();
+
+        let mut out = Vec::new();
+        let mut offset = 0;
+        for (width, height, depth) in image.images() {
+            let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+            let len = (blocks_x * blocks_y * 16) as usize;
+            for _ in 0..depth {
+                let Some(blocks) = image.data.get(offset..offset + len) else {
+                    return Err(TextureError::Other("UASTC texture data is shorter than its header describes".into()));
+                };
+                offset += len;
+
+                let params = SliceParametersUastc {
+                    num_blocks_x: blocks_x,
+                    num_blocks_y: blocks_y,
+                    has_alpha: uastc.has_alpha,
+                    original_width: width,
+                    original_height: height,
+                };
+                let slice = transcoder.transcode_slice(blocks, params, DecodeFlags::HIGH_QUALITY, block_format)
+                    .map_err(|e| TextureError::Other(format!("failed to transcode UASTC data to {format:?}: {e:?}")))?;
+                out.extend(slice);
+            }
+        }
+        Ok((format, out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress::{decode_block, decompress_all, decompressed_format};
+    use super::ContainerImage;
+
+    fn decode(format: wgpu::TextureFormat, block: &[u8]) -> Vec<[u8; 4]> {
+        let (width, height) = format.block_dimensions();
+        let mut texels = vec![[0; 4]; (width * height) as usize];
+        decode_block(format, block, &mut texels);
+        texels
+    }
+
+    /// ASTC void extent block, every texel is the given UNORM16 color
+    fn astc_solid(rgba: [u16; 4]) -> [u8; 16] {
+        let mut block = [0xFF; 16];
+        block[..2].copy_from_slice(&[0xFC, 0xFD]);
+        for (i, c) in rgba.iter().enumerate() {
+            block[8 + i * 2..10 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        // red and blue endpoints, each row uses indices 0 1 2 3
+        let texels = decode(wgpu::TextureFormat::Bc1RgbaUnorm, &[0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4]);
+        for row in texels.chunks(4) {
+            assert_eq!(row, [[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]);
+        }
+    }
+
+    #[test]
+    fn bc1_three_colors_and_transparent() {
+        // c0 <= c1 switches to the midpoint plus transparent black
+        let texels = decode(wgpu::TextureFormat::Bc1RgbaUnorm, &[0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4]);
+        assert_eq!(texels[..4], [[0, 0, 255, 255], [255, 0, 0, 255], [127, 0, 127, 255], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn bc2_and_bc3_alpha() {
+        let color = [0x00, 0xF8, 0x1F, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+        let mut bc2 = vec![0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE];
+        bc2.extend(color);
+        let texels = decode(wgpu::TextureFormat::Bc2RgbaUnorm, &bc2);
+        assert!(texels.iter().enumerate().all(|(t, texel)| *texel == [255, 0, 0, t as u8 * 17]));
+
+        // 8 value alpha palette, the first row uses indices 0 1 2 7
+        let mut bc3 = vec![255, 0, 0b1000_1000, 0b0000_1110, 0, 0, 0, 0];
+        bc3.extend(color);
+        let texels = decode(wgpu::TextureFormat::Bc3RgbaUnorm, &bc3);
+        let alpha: Vec<u8> = texels[..4].iter().map(|t| t[3]).collect();
+        assert_eq!(alpha, [255, 0, 218, 36]);
+        assert_eq!(texels[0][..3], [255, 0, 0]);
+    }
+
+    #[test]
+    fn bc4_and_bc5_channels() {
+        // 6 value palette plus 0 and 255, the first row uses indices 0 2 6 7
+        let bc4 = [0, 100, 0b1001_0000, 0b0000_1111, 0, 0, 0, 0];
+        let texels = decode(wgpu::TextureFormat::Bc4RUnorm, &bc4);
+        assert_eq!(texels[..4], [[0, 0, 0, 255], [20, 0, 0, 255], [0, 0, 0, 255], [255, 0, 0, 255]]);
+
+        let mut bc5 = bc4.to_vec();
+        bc5.extend([200, 200, 0, 0, 0, 0, 0, 0]);
+        let texels = decode(wgpu::TextureFormat::Bc5RgUnorm, &bc5);
+        assert_eq!(texels[1], [20, 200, 0, 255]);
+    }
+
+    #[test]
+    fn etc2_individual_mode() {
+        // both halves 0x88 0x44 0x00 with modifier table 0, index 0 adds 2
+        let texels = decode(wgpu::TextureFormat::Etc2Rgb8Unorm, &[0x88, 0x44, 0x00, 0x00, 0, 0, 0, 0]);
+        assert!(texels.iter().all(|texel| *texel == [138, 70, 2, 255]));
+    }
+
+    #[test]
+    fn astc_void_extent_swizzles_to_rgba() {
+        for block in [wgpu::AstcBlock::B4x4, wgpu::AstcBlock::B8x6, wgpu::AstcBlock::B12x12] {
+            let format = wgpu::TextureFormat::Astc { block, channel: wgpu::AstcChannel::Unorm };
+            let texels = decode(format, &astc_solid([0xFFFF, 0x8080, 0x0000, 0x4040]));
+            assert!(texels.iter().all(|texel| *texel == [255, 128, 0, 64]), "{block:?}");
+        }
+    }
+
+    #[test]
+    fn decompress_crops_partial_blocks_and_walks_mips() {
+        let format = wgpu::TextureFormat::Astc { block: wgpu::AstcBlock::B4x4, channel: wgpu::AstcChannel::UnormSrgb };
+        assert_eq!(decompressed_format(format), Some(wgpu::TextureFormat::Rgba8UnormSrgb));
+
+        // 6x5 needs 2x2 blocks, its 3x2 mip 1
+        let colors = [[0xFFFF, 0, 0, 0xFFFF], [0, 0xFFFF, 0, 0xFFFF], [0, 0, 0xFFFF, 0xFFFF], [0xFFFF; 4], [0; 4]];
+        let image = ContainerImage {
+            format,
+            size: wgpu::Extent3d { width: 6, height: 5, depth_or_array_layers: 1 },
+            dimension: wgpu::TextureDimension::D2,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            mip_level_count: 2,
+            order: wgpu::util::TextureDataOrder::MipMajor,
+            data: colors.into_iter().flat_map(astc_solid).collect(),
+            #[cfg(feature = "basis")]
+            uastc: None,
+        };
+
+        let out = decompress_all(&image).unwrap();
+        assert_eq!(out.len(), (6 * 5 + 3 * 2) * 4);
+        let texel = |i: usize| &out[i * 4..i * 4 + 4];
+        assert_eq!(texel(3), [255, 0, 0, 255]);
+        assert_eq!(texel(4), [0, 255, 0, 255]);
+        assert_eq!(texel(6 * 4 + 5), [255, 255, 255, 255]);
+        assert_eq!(texel(30), [0, 0, 0, 0]);
+
+        let short = ContainerImage { data: image.data[..64].to_vec(), ..image };
+        assert!(decompress_all(&short).is_err());
+    }
+
+    #[test]
+    fn uncompressed_formats_stay_on_the_gpu() {
+        assert_eq!(decompressed_format(wgpu::TextureFormat::Rgba8Unorm), None);
+        assert_eq!(decompressed_format(wgpu::TextureFormat::Bc6hRgbUfloat), None);
+        assert_eq!(decompressed_format(wgpu::TextureFormat::Bc7RgbaUnormSrgb), Some(wgpu::TextureFormat::Rgba8UnormSrgb));
+    }
+
+    /// A KTX2 file holding one BC1 block of `width` by `height` texels
+    fn ktx2_bc1(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = vec![0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+        // VK_FORMAT_BC1_RGBA_UNORM_BLOCK, type size, width, height, depth, layers, faces, levels, supercompression
+        for value in [133, 1, width, height, 0, 0, 1, 1, 0] {
+            bytes.extend(u32::to_le_bytes(value));
+        }
+        // an empty data format descriptor after the level index, no key/value or supercompression data
+        bytes.extend(104u32.to_le_bytes());
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend([0; 24]);
+        for value in [108u64, 8, 8] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes
+    }
+
+    fn dds_bc1(width: u32, height: u32) -> Vec<u8> {
+        let mut dds = ddsfile::Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height,
+            width,
+            depth: None,
+            format: ddsfile::DxgiFormat::BC1_UNorm,
+            mipmap_levels: None,
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: ddsfile::D3D10ResourceDimension::Texture2D,
+            alpha_mode: ddsfile::AlphaMode::Unknown,
+        }).unwrap();
+        dds.data = vec![0; 8];
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ktx2_rejects_zero_width() {
+        let image = ContainerImage::from_ktx2(&ktx2_bc1(4, 4)).unwrap();
+        assert_eq!(image.size, wgpu::Extent3d { width: 4, height: 4, depth_or_array_layers: 1 });
+
+        // a height of 0 marks a 1D texture, but a width of 0 is never valid
+        assert_eq!(ContainerImage::from_ktx2(&ktx2_bc1(4, 0)).unwrap().size.height, 1);
+        assert!(ContainerImage::from_ktx2(&ktx2_bc1(0, 4)).is_err());
+    }
+
+    #[test]
+    fn dds_rejects_zero_width() {
+        let image = ContainerImage::from_dds(&dds_bc1(4, 4)).unwrap();
+        assert_eq!(image.size, wgpu::Extent3d { width: 4, height: 4, depth_or_array_layers: 1 });
+
+        let error = ContainerImage::from_dds(&dds_bc1(0, 4)).err().expect("zero width should be rejected");
+        assert!(error.to_string().contains("empty 0x4x1 texture"), "{error}");
+    }
+}
@@ -0,0 +1,212 @@
+use glam::UVec2;
+use image::GenericImageView;
+
+use crate::{decode::{decode_image_file, is_high_precision}, gpu::Gpu, texture::{Texture, TextureError}};
+
+/// Number of faces (array layers) in a cube texture
+pub const CUBE_FACES: u32 = 6;
+
+impl Gpu {
+    /// Create an empty cube texture with `face_size` by `face_size` faces, viewed as `TextureViewDimension::Cube`
+    ///
+    /// `TEXTURE_BINDING` is always added to `usage`
+    pub fn new_cubemap(&self, face_size: u32, format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> Texture {
+        let desc = wgpu::TextureDescriptor {
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: CUBE_FACES,
+            },
+            label: None,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+
+        self.new_texture_from_desc(&desc, wgpu::TextureViewDimension::Cube)
+    }
+
+    /// Load a cubemap from six square images of the same size, in the order +X, -X, +Y, -Y, +Z, -Z
+    ///
+    /// If any face has more than 8 bits per channel (`.hdr`, `.exr`, 16 bit pngs) the cubemap is `Rgba16Float`,
+    /// otherwise it is `Rgba8UnormSrgb`
+    pub fn new_cubemap_from_files(&self, paths: [&str; 6]) -> Result<Texture, TextureError> {
+        let faces = paths.iter()
+            .map(|path| decode_image_file(path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let size = faces[0].width();
+        for (path, face) in paths.iter().zip(&faces) {
+            if face.dimensions() != (size, size) {
+                return Err(TextureError::Other(format!(
+                    "cubemap faces must be square and the same size, expected {size}x{size} but {path} is {}x{}",
+                    face.width(), face.height()
+                )));
+            }
+        }
+        self.check_face_size(size)?;
+
+        let (format, bytes) = if faces.iter().any(is_high_precision) {
+            let texels: Vec<half::f16> = faces.iter()
+                .flat_map(|face| face.to_rgba32f().into_raw())
+                .map(half::f16::from_f32)
+                .collect();
+            (wgpu::TextureFormat::Rgba16Float, bytemuck::cast_slice(&texels).to_vec())
+        } else {
+            let texels: Vec<u8> = faces.iter()
+                .flat_map(|face| face.to_rgba8().into_raw())
+                .collect();
+            (wgpu::TextureFormat::Rgba8UnormSrgb, texels)
+        };
+
+        let cube = self.new_cubemap(size, format, wgpu::TextureUsages::COPY_DST);
+        self.queue.write_texture(
+            cube.raw.as_image_copy(),
+            &bytes,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size * format.target_pixel_byte_cost().unwrap_or(4)),
+                rows_per_image: Some(size),
+            },
+            cube.raw.size(),
+        );
+        Ok(cube)
+    }
+
+    /// Load an equirectangular (latitude-longitude) image and convert it to an `Rgba16Float` cubemap on the GPU
+    ///
+    /// Any format the `image` crate decodes works, but `.hdr` and `.exr` are the usual choice for environment lighting.
+    /// The image has to fit in `max_texture_dimension_2d`, which large HDRIs often don't with the default limits
+    pub fn new_cubemap_from_equirect(&self, path: &str, face_size: u32) -> Result<Texture, TextureError> {
+        self.check_face_size(face_size)?;
+        let image = decode_image_file(path)?.to_rgba32f();
+        let (width, height) = image.dimensions();
+        let max = self.device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 || width > max || height > max {
+            return Err(TextureError::Other(format!(
+                "equirect image {path} is {width}x{height}, but textures must be between 1 and {max} texels on a side"
+            )));
+        }
+
+        let equirect = self.new_texture(UVec2::new(width, height), wgpu::TextureFormat::Rgba32Float, false);
+        self.queue.write_texture(
+            equirect.raw.as_image_copy(),
+            bytemuck::cast_slice(image.as_raw()),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 16),
+                rows_per_image: None,
+            },
+            equirect.raw.size(),
+        );
+
+        self.equirect_to_cubemap(&equirect, face_size)
+    }
+
+    fn check_face_size(&self, face_size: u32) -> Result<(), TextureError> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if face_size == 0 || face_size > max {
+            return Err(TextureError::Other(format!("cubemap face size must be between 1 and {max}, got {face_size}")));
+        }
+        Ok(())
+    }
+
+    /// Project a 2D equirectangular texture onto the faces of a new `Rgba16Float` cubemap with a compute pass
+    ///
+    /// The texture is read with `textureLoad`, so unfilterable formats like `Rgba32Float` are fine, but it has to be
+    /// a single sampled, single layer D2 texture with a float format and `TEXTURE_BINDING` usage
+    pub fn equirect_to_cubemap(&self, equirect: &Texture, face_size: u32) -> Result<Texture, TextureError> {
+        self.check_face_size(face_size)?;
+        let view = equirect.view_all();
+        if !matches!(view.format.sample_type(None, None), Some(wgpu::TextureSampleType::Float { .. })) {
+            return Err(TextureError::Other(format!("equirect texture must have a float sampleable format, got {:?}", view.format)));
+        }
+        if view.dimension != wgpu::TextureViewDimension::D2 {
+            return Err(TextureError::Other(format!("equirect texture must be viewed as D2, got {:?}", view.dimension)));
+        }
+        if equirect.raw.sample_count() != 1 {
+            return Err(TextureError::Other("equirect texture must be single sampled".into()));
+        }
+        if !equirect.raw.usage().contains(wgpu::TextureUsages::TEXTURE_BINDING) {
+            return Err(TextureError::Other("equirect texture needs TEXTURE_BINDING usage".into()));
+        }
+
+        let cube = self.new_cubemap(face_size, wgpu::TextureFormat::Rgba16Float, wgpu::TextureUsages::STORAGE_BINDING);
+        let faces = cube.raw.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Cubemap faces storage view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let layout = self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Equirect to cubemap"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Equirect to cubemap"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&equirect.view_all().raw),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&faces),
+                },
+            ],
+        });
+
+        let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Equirect to cubemap"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let module = self.device.create_shader_module(wgpu::include_wgsl!("shaders/equirect_to_cube.wgsl"));
+        let pipeline = self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Equirect to cubemap"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(face_size.div_ceil(8), face_size.div_ceil(8), CUBE_FACES);
+        }
+        self.queue.submit([encoder.finish()]);
+
+        Ok(cube)
+    }
+}
@@ -0,0 +1,179 @@
+use glam::UVec2;
+use image::DynamicImage;
+
+use crate::{compressed::ContainerImage, fetch_bytes, gpu::Gpu, texture::{Texture, TextureError}};
+
+/// Image data decoded on the CPU and ready to upload. Decoding is the slow part of loading a texture,
+/// so this is `Send` and can be produced on any thread
+pub enum DecodedImage {
+    /// Pixels from the `image` crate, converted to a format wgpu can sample
+    Pixels {
+        format: wgpu::TextureFormat,
+        size: UVec2,
+        data: Vec<u8>,
+    },
+    /// A KTX2 or DDS file with all of its mips and layers
+    Container(ContainerImage),
+}
+
+pub(crate) fn decode_image_file(path: &str) -> Result<DynamicImage, TextureError> {
+    let reader = image::ImageReader::open(path)?.with_guessed_format()?;
+    Ok(reader.decode()?)
+}
+
+/// true for images with more than 8 bits per channel, like `.hdr` and `.exr` files
+pub(crate) fn is_high_precision(image: &DynamicImage) -> bool {
+    let color = image.color();
+    color.bytes_per_pixel() / color.channel_count() > 1
+}
+
+pub(crate) fn to_f16_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    let texels: Vec<half::f16> = values.map(half::f16::from_f32).collect();
+    bytemuck::cast_slice(&texels).to_vec()
+}
+
+/// Convert a decoded image to texels wgpu can sample. RGB is widened to RGBA and 16 bit channels become floats
+pub fn image_to_texels(image: &DynamicImage) -> (wgpu::TextureFormat, Vec<u8>) {
+    match image {
+        DynamicImage::ImageLuma8(img)      => (wgpu::TextureFormat::R8Unorm, img.as_raw().clone()),
+        DynamicImage::ImageLumaA8(img)     => (wgpu::TextureFormat::Rg8Unorm, img.as_raw().clone()),
+        DynamicImage::ImageRgb8(_)         => (wgpu::TextureFormat::Rgba8UnormSrgb, image.to_rgba8().into_raw()),
+        DynamicImage::ImageRgba8(img)      => (wgpu::TextureFormat::Rgba8UnormSrgb, img.as_raw().clone()),
+        DynamicImage::ImageLuma16(img)     => (wgpu::TextureFormat::R32Float, bytemuck::cast_slice(
+            &img.as_raw().iter().map(|&v| v as f32 / u16::MAX as f32).collect::<Vec<f32>>()
+        ).to_vec()),
+        DynamicImage::ImageLumaA16(img)    => (wgpu::TextureFormat::Rg16Float, to_f16_bytes(
+            img.as_raw().iter().map(|&v| v as f32 / u16::MAX as f32)
+        )),
+        DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_)     => (wgpu::TextureFormat::Rgba16Float, to_f16_bytes(image.to_rgba32f().into_raw().into_iter())),
+        _                                  => (wgpu::TextureFormat::Rgba32Float, bytemuck::cast_slice(image.to_rgba32f().as_raw()).to_vec()),
+    }
+}
+
+/// Decode an encoded image. KTX2 and DDS files are recognized by their magic bytes, everything else goes
+/// through the `image` crate, using `hint` if given and guessing the format from the contents otherwise
+pub fn decode_image_bytes(bytes: &[u8], hint: Option<image::ImageFormat>) -> Result<DecodedImage, TextureError> {
+    const KTX2_MAGIC: &[u8] = b"\xABKTX 20\xBB";
+    const DDS_MAGIC: &[u8] = b"DDS ";
+
+    if bytes.starts_with(KTX2_MAGIC) {
+        return Ok(DecodedImage::Container(ContainerImage::from_ktx2(bytes)?));
+    }
+    if bytes.starts_with(DDS_MAGIC) {
+        return Ok(DecodedImage::Container(ContainerImage::from_dds(bytes)?));
+    }
+
+    let image = match hint {
+        Some(format) => image::load_from_memory_with_format(bytes, format)?,
+        None => image::load_from_memory(bytes)?,
+    };
+    let (format, data) = image_to_texels(&image);
+    Ok(DecodedImage::Pixels {
+        format,
+        size: UVec2::new(image.width(), image.height()),
+        data,
+    })
+}
+
+/// Decode on a background thread on native targets, or inline on the web where there are no threads to use
+pub async fn decode_image_bytes_async(bytes: Vec<u8>, hint: Option<image::ImageFormat>) -> Result<DecodedImage, TextureError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        background::spawn(move || decode_image_bytes(&bytes, hint)).await
+            .unwrap_or_else(|panic| Err(TextureError::Other(format!("image decoding panicked: {panic}"))))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        decode_image_bytes(&bytes, hint)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod background {
+    use std::{any::Any, future::Future, panic::{self, AssertUnwindSafe}, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll, Waker}};
+
+    /// The closure's value or panic message once it has finished, and the waker of whoever is waiting for it
+    type Shared<T> = Arc<Mutex<(Option<Result<T, String>>, Option<Waker>)>>;
+
+    /// Result of a closure running on its own thread, so it can be awaited without an executor specific spawn.
+    /// Resolves to the panic message if the closure panics
+    pub struct BackgroundTask<T> {
+        shared: Shared<T>,
+    }
+
+    fn panic_message(payload: Box<dyn Any + Send>) -> String {
+        match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or("unknown panic", |message| message).to_string(),
+        }
+    }
+
+    pub fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> BackgroundTask<T> {
+        let shared = Arc::new(Mutex::new((None, None::<Waker>)));
+        let thread_shared = shared.clone();
+        std::thread::spawn(move || {
+            let value = panic::catch_unwind(AssertUnwindSafe(f)).map_err(panic_message);
+            let mut state = thread_shared.lock().unwrap();
+            state.0 = Some(value);
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        });
+        BackgroundTask { shared }
+    }
+
+    impl<T> Future for BackgroundTask<T> {
+        type Output = Result<T, String>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut state = self.shared.lock().unwrap();
+            match state.0.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    state.1 = Some(cx.waker().clone());
+                    Poll::Pending
+                },
+            }
+        }
+    }
+}
+
+impl Gpu {
+    /// Create a texture from an encoded image in memory, see `decode_image_bytes`
+    pub fn new_texture_from_bytes(&self, bytes: &[u8], hint: Option<image::ImageFormat>) -> Result<Texture, TextureError> {
+        let image = decode_image_bytes(bytes, hint)?;
+        self.new_texture_from_decoded(image)
+    }
+
+    pub fn new_texture_from_decoded(&self, image: DecodedImage) -> Result<Texture, TextureError> {
+        match image {
+            DecodedImage::Container(container) => self.new_texture_from_container(container),
+            DecodedImage::Pixels { format, size, data } => {
+                let tex = self.new_texture(size, format, false);
+                self.queue.write_texture(
+                    tex.raw.as_image_copy(),
+                    &data,
+                    wgpu::TexelCopyBufferLayout {
+                        bytes_per_row: Some(size.x * format.target_pixel_byte_cost().unwrap_or(4)),
+                        rows_per_image: None,
+                        offset: 0,
+                    },
+                    tex.raw.size(),
+                );
+                Ok(tex)
+            },
+        }
+    }
+
+    /// Load a texture through `fetch_bytes`, so the same path works natively and in the browser.
+    /// Decoding runs on a background thread on native targets
+    pub async fn load_texture(&self, path: &str) -> Result<Texture, TextureError> {
+        let bytes = fetch_bytes(path).await
+            .ok_or_else(|| TextureError::Other(format!("failed to fetch {path}")))?;
+        let hint = image::ImageFormat::from_path(path).ok();
+        let image = decode_image_bytes_async(bytes, hint).await?;
+        self.new_texture_from_decoded(image)
+    }
+}
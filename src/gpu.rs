@@ -1,11 +1,29 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use bytemuck::bytes_of;
 use glam::UVec2;
-use image::GenericImageView;
 use winit::window::Window;
 
-use crate::{bindgroup::{BGBuilder, BindGroup}, buffer::Buffer, resource::ResourceManager, texture::{Texture, TextureError, TextureView}};
+use crate::{bindgroup::{BGBuilder, BindGroup}, blit::Blitter, buffer::Buffer, diagnostics::Diagnostic, resource::ResourceManager, texture::{Texture, TextureError, TextureView}, texture_array::full_mip_count};
+
+/// An uncaptured wgpu error as a `Diagnostic`, the rest of its source chain becomes the notes
+fn error_diagnostic(error: &wgpu::Error) -> Diagnostic {
+    let mut source: &dyn std::error::Error = error;
+    let message = match source.source() {
+        Some(inner) => {
+            source = inner;
+            inner.to_string()
+        },
+        None => error.to_string(),
+    };
+
+    let mut notes = Vec::new();
+    while let Some(next) = source.source() {
+        notes.push(next.to_string());
+        source = next;
+    }
+    Diagnostic { message, labels: Vec::new(), notes }
+}
 
 /// Helper struct to hold the core wgpu resources in one place so they are easier 
 /// to construct and pass around
@@ -16,12 +34,15 @@ pub struct Gpu {
     pub surface: wgpu::Surface<'static>,
     pub surface_config: wgpu::SurfaceConfiguration,
     pub window:  Arc<Window>,
+    pub(crate) blitter: OnceLock<Blitter>,
 }
 
 
 impl Gpu {
     pub fn new_bind_group<'a>(&'a self) -> BGBuilder<'a> {
-        BGBuilder::new(&self.device)
+        let mut builder = BGBuilder::new(&self.device);
+        builder.with_adapter(&self.adapter);
+        builder
     }
 
     pub fn new_pipeline_layout(&self, resources: &ResourceManager, bind_groups: &[&BindGroup]) -> wgpu::PipelineLayout {
@@ -29,17 +50,16 @@ impl Gpu {
             .map(|bg| resources.get_bind_group_layout(&bg.entries).unwrap())
             .collect();
 
-        let layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &layouts,
             push_constant_ranges: &[],
-        });
-        layout
+        })
     }
 
     pub fn new_texture(&self, size: UVec2, format: wgpu::TextureFormat, renderable: bool) -> Texture {
         let usage = if renderable {
-            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC
         } else {
             wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING
         };
@@ -59,11 +79,18 @@ impl Gpu {
             view_formats: &[],
         };
 
+        self.new_texture_from_desc(&desc, wgpu::TextureViewDimension::D2)
+    }
+
+    /// Create a texture from a full descriptor, with a default label and a view of the whole texture
+    /// using `dim` as the view dimension
+    pub fn new_texture_from_desc(&self, desc: &wgpu::TextureDescriptor, dim: wgpu::TextureViewDimension) -> Texture {
         let mut tex = Texture {
-            raw: self.device.create_texture(&desc),
+            raw: self.device.create_texture(desc),
             label: None,
-            dim: wgpu::TextureViewDimension::D2,
+            dim,
             views: Vec::new(),
+            generation: Default::default(),
         };
 
         tex.label = Some(tex.default_label());
@@ -71,9 +98,18 @@ impl Gpu {
         tex
     }
 
+    /// Reconfigure the surface for a new window size, call this on `WindowEvent::Resized`
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.surface_config.width = width.max(1);
+        self.surface_config.height = height.max(1);
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
     pub fn get_surface_view(&self, surface_texture: &wgpu::SurfaceTexture) -> TextureView {
-        let mut surface_view_desc = wgpu::TextureViewDescriptor::default();
-        surface_view_desc.format =  Some(self.surface_config.view_formats.iter().find(|f| f.is_srgb()).copied().unwrap_or(self.surface_config.format));
+        let surface_view_desc = wgpu::TextureViewDescriptor {
+            format: Some(self.surface_config.view_formats.iter().find(|f| f.is_srgb()).copied().unwrap_or(self.surface_config.format)),
+            ..Default::default()
+        };
         let view = surface_texture.texture.create_view(&surface_view_desc);
         TextureView {
             raw: view,
@@ -84,42 +120,17 @@ impl Gpu {
             mip_level_count: surface_view_desc.mip_level_count,
             base_array_layer: surface_view_desc.base_array_layer,
             array_layer_count: surface_view_desc.array_layer_count,
+            generation: Default::default(),
         }
     }
 
+    /// Load an image file as a texture. `.ktx2` and `.dds` files are loaded with all of their mips and layers,
+    /// anything else is decoded by the `image` crate
+    /// 
+    /// Reads from the filesystem, use `load_texture` for something that also works on the web
     pub fn new_texture_from_file(&self, path: &str) -> Result<Texture, TextureError> {
-        let reader = image::ImageReader::open(path)?.with_guessed_format()?;
-        let image = reader.decode()?;
-        let dim = image.dimensions();
-        let (format, bytes_per_pixel) = match image {
-            image::DynamicImage::ImageLuma8(_)      => (wgpu::TextureFormat::R8Unorm, 1),
-            image::DynamicImage::ImageLumaA8(_)     => (wgpu::TextureFormat::Rg8Unorm, 2),
-            image::DynamicImage::ImageRgb8(_)       => (wgpu::TextureFormat::Rgba8UnormSrgb, 4),
-            image::DynamicImage::ImageRgba8(_)      => (wgpu::TextureFormat::Rgba8UnormSrgb, 4),
-            image::DynamicImage::ImageLuma16(_)     => (wgpu::TextureFormat::R32Float, 4),
-            image::DynamicImage::ImageLumaA16(_)    => (wgpu::TextureFormat::Rg16Float, 4),
-            image::DynamicImage::ImageRgb16(_)      => (wgpu::TextureFormat::Rgba16Float, 8),
-            image::DynamicImage::ImageRgba16(_)     => (wgpu::TextureFormat::Rgba16Float, 8),
-            image::DynamicImage::ImageRgb32F(_)     => (wgpu::TextureFormat::Rgba32Float, 16),
-            image::DynamicImage::ImageRgba32F(_)    => (wgpu::TextureFormat::Rgba32Float, 16),
-            _ => (wgpu::TextureFormat::Rgba32Float, 16),
-        };
-        let tex = self.new_texture(dim.into(), format, false);
-        self.queue.write_texture(
-            tex.raw.as_image_copy(), 
-            image.as_bytes(), 
-            wgpu::TexelCopyBufferLayout {
-                bytes_per_row: Some(dim.0 * bytes_per_pixel),
-                rows_per_image: None,
-                offset: 0,
-            }, 
-            wgpu::Extent3d {
-                width: dim.0,
-                height: dim.1,
-                depth_or_array_layers: 1,
-            }
-        );
-        Ok(tex)
+        let bytes = std::fs::read(path)?;
+        self.new_texture_from_bytes(&bytes, image::ImageFormat::from_path(path).ok())
     }
 
 
@@ -136,6 +147,7 @@ impl Gpu {
         self.queue.write_buffer(&buffer, 0, bytes_of(val));
         Buffer {
             raw: buffer,
+            generation: Default::default(),
         }
     }
 
@@ -154,7 +166,69 @@ impl Gpu {
 
         Buffer {
             raw: self.device.create_buffer(&desc),
+            generation: Default::default(),
+        }
+    }
+
+    /// Recreate a texture at a new size, keeping its format, usage, layer count and views. The contents are lost.
+    /// A texture with a full mip chain gets a full chain for the new size, otherwise the mip count is kept where it fits
+    ///
+    /// Bind groups built from the texture become stale, see `BindGroup::is_stale`
+    pub fn resize_texture(&self, texture: &mut Texture, size: UVec2) {
+        let old_size = texture.raw.size();
+        if (old_size.width, old_size.height) == (size.x, size.y) {
+            return;
+        }
+
+        let full_mips = full_mip_count(size.x, size.y);
+        let mip_level_count = if texture.raw.mip_level_count() == full_mip_count(old_size.width, old_size.height) {
+            full_mips
+        } else {
+            texture.raw.mip_level_count().min(full_mips)
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: texture.label.as_deref(),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: old_size.depth_or_array_layers,
+            },
+            mip_level_count,
+            sample_count: texture.raw.sample_count(),
+            dimension: texture.raw.dimension(),
+            format: texture.raw.format(),
+            usage: texture.raw.usage(),
+            view_formats: &[],
+        };
+        texture.replace_raw(self.device.create_texture(&desc));
+    }
+
+    /// Replace a buffer with a larger one of the same usage, copying the old contents over when the buffer has
+    /// `COPY_SRC` and `COPY_DST` usage. Does nothing if the buffer is already at least `size` bytes
+    ///
+    /// Bind groups built from the buffer become stale, see `BindGroup::is_stale`
+    pub fn grow_buffer(&self, buffer: &mut Buffer, size: u64) {
+        if buffer.raw.size() >= size {
+            return;
+        }
+
+        let usage = buffer.raw.usage();
+        let grown = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            mapped_at_creation: false,
+            size,
+            usage,
+        });
+
+        if usage.contains(wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST) {
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            encoder.copy_buffer_to_buffer(&buffer.raw, 0, &grown, 0, buffer.raw.size());
+            self.queue.submit([encoder.finish()]);
         }
+
+        buffer.raw = grown;
+        buffer.generation.bump();
     }
 
 
@@ -185,9 +259,26 @@ impl Gpu {
         limits.max_buffer_size = adapter.limits().max_buffer_size;
         limits.max_storage_buffer_binding_size = adapter.limits().max_storage_buffer_binding_size;
 
+        // enable whichever block compression formats the adapter has, container loading falls back to the CPU without them
+        let compression_features = wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC_HDR;
+
+        // lets storage textures use every access mode the adapter supports for a format, not just the WebGPU guaranteed ones
+        let format_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+
+        // binding arrays, `BGBuilder::finish` reports an error when one is used without them
+        let binding_array_features = wgpu::Features::TEXTURE_BINDING_ARRAY
+            | wgpu::Features::BUFFER_BINDING_ARRAY
+            | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY
+            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING;
+        limits.max_binding_array_elements_per_shader_stage = adapter.limits().max_binding_array_elements_per_shader_stage;
+        limits.max_binding_array_sampler_elements_per_shader_stage = adapter.limits().max_binding_array_sampler_elements_per_shader_stage;
+
         let device_desc = wgpu::DeviceDescriptor {
             label: None,
-            required_features: wgpu::Features::empty(),
+            required_features: adapter.features() & (compression_features | format_features | binding_array_features),
             // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
             required_limits: limits,
             memory_hints: wgpu::MemoryHints::MemoryUsage,
@@ -200,24 +291,15 @@ impl Gpu {
             .expect("Failed to create device");
 
 
-        device.on_uncaptured_error(Box::new(
-            |error| 
-            {
-                match &error {
-                    wgpu::Error::Validation { source: _, description } => {
-
-                        println!("{description}");
-                        if description.contains("Device::create_shader_module") {
-                            return;
-                        }
-                    },
-                    _ => (),
-                }
-                println!("Panicking due to uncaptured wgpu error");
-                panic!();
+        device.on_uncaptured_error(Box::new(|error| {
+            eprint!("{}", error_diagnostic(&error).render());
+            // invalid shader modules are reported but not fatal
+            if let wgpu::Error::Validation { description, .. } = &error
+                && description.contains("Device::create_shader_module") {
+                return;
             }
-        
-        ));
+            panic!("uncaptured wgpu error");
+        }));
 
         let mut surface_config = surface
             .get_default_config(&adapter, size.width, size.height)
@@ -241,8 +323,41 @@ impl Gpu {
             surface,
             surface_config,
             window,
+            blitter: OnceLock::new(),
         })
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Chain(&'static str, Option<Box<Chain>>);
+
+    impl std::fmt::Display for Chain {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl std::error::Error for Chain {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1.as_deref().map(|e| e as _)
+        }
+    }
+
+    #[test]
+    fn uncaptured_errors_become_diagnostics() {
+        let error = wgpu::Error::Validation {
+            source: Box::new(Chain("In Device::create_bind_group", Some(Box::new(Chain("buffer is too small", None))))),
+            description: "Validation Error\n\nCaused by:\n  In Device::create_bind_group\n    buffer is too small\n".into(),
+        };
+        let diagnostic = error_diagnostic(&error);
+        assert_eq!(diagnostic.message, "In Device::create_bind_group");
+        assert_eq!(diagnostic.notes, ["buffer is too small"]);
+        assert_eq!(diagnostic.render(), "error: In Device::create_bind_group\n  = note: buffer is too small\n");
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use glam::UVec2;
+use wgpu::util::DeviceExt;
+
+use crate::{decode::{decode_image_file, to_f16_bytes}, gpu::Gpu, texture::{Texture, TextureError, TextureView}};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ToneMapOperator {
+    /// No curve, values above 1 are clipped
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// Minimal AgX, desaturates highlights instead of skewing their hue
+    #[default]
+    AgX,
+}
+
+// the params cache is cleared once it holds this many settings
+const MAX_CACHED_PARAMS: usize = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapParams {
+    exposure: f32,
+    curve: u32,
+    encode_srgb: u32,
+    _pad: u32,
+}
+
+/// Params buffer for one set of settings, with a bind group for the last source view it was used with
+struct CachedParams {
+    buffer: wgpu::Buffer,
+    bind_group: Option<(wgpu::TextureView, wgpu::BindGroup)>,
+}
+
+/// Fullscreen pass that maps a linear HDR texture to an LDR target, usually the view from `get_surface_view`
+///
+/// Pipelines are created lazily for each target format. sRGB targets are written linear and encoded by the
+/// hardware, other targets get the sRGB curve applied in the shader
+///
+/// Each distinct set of settings gets its own uniform buffer, so passes recorded before the same submit can use
+/// different exposures, operators and target formats
+pub struct ToneMapper {
+    layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    module: wgpu::ShaderModule,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+    params: HashMap<[u32; 4], CachedParams>,
+    pub operator: ToneMapOperator,
+    /// In stops, the input is multiplied by 2^exposure
+    pub exposure: f32,
+}
+
+impl ToneMapper {
+    pub fn new(gpu: &Gpu) -> Self {
+        let layout = gpu.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone mapping"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = gpu.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone mapping"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            layout,
+            pipeline_layout,
+            module: gpu.device.create_shader_module(wgpu::include_wgsl!("shaders/tonemap.wgsl")),
+            pipelines: HashMap::new(),
+            params: HashMap::new(),
+            operator: ToneMapOperator::default(),
+            exposure: 0.0,
+        }
+    }
+
+    fn pipeline(&mut self, gpu: &Gpu, format: wgpu::TextureFormat) -> &wgpu::RenderPipeline {
+        self.pipelines.entry(format).or_insert_with(|| {
+            gpu.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Tone mapping"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.module,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        })
+    }
+
+    /// Record a pass tone mapping `source` into `target`, stretching it to the target size
+    pub fn apply(&mut self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder, source: &TextureView, target: &TextureView) {
+        let params = ToneMapParams {
+            exposure: self.exposure,
+            curve: self.operator as u32,
+            encode_srgb: (!target.format.is_srgb()) as u32,
+            _pad: 0,
+        };
+        let key: [u32; 4] = bytemuck::cast(params);
+        // exposure can change every frame, so don't let old settings pile up. Recorded passes keep their buffers alive
+        if self.params.len() >= MAX_CACHED_PARAMS && !self.params.contains_key(&key) {
+            self.params.clear();
+        }
+        let CachedParams { buffer, bind_group } = self.params.entry(key).or_insert_with(|| CachedParams {
+            buffer: gpu.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tone mapping params"),
+                contents: bytemuck::bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }),
+            bind_group: None,
+        });
+
+        if bind_group.as_ref().is_none_or(|(view, _)| *view != source.raw) {
+            let group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Tone mapping"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.raw),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            });
+            *bind_group = Some((source.raw.clone(), group));
+        }
+        let bind_group = bind_group.as_ref().unwrap().1.clone();
+
+        self.pipeline(gpu, target.format);
+        let pipeline = &self.pipelines[&target.format];
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone mapping"),
+            color_attachments: &[Some(target.attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+impl Gpu {
+    /// Load an image as linear float data, `format` must be `Rgba16Float` or `Rgba32Float`
+    ///
+    /// Meant for `.hdr` and `.exr` files, but LDR images are converted to float as well
+    pub fn new_hdr_texture_from_file(&self, path: &str, format: wgpu::TextureFormat) -> Result<Texture, TextureError> {
+        let image = decode_image_file(path)?.to_rgba32f();
+        let size = UVec2::new(image.width(), image.height());
+        let data = match format {
+            wgpu::TextureFormat::Rgba32Float => bytemuck::cast_slice(image.as_raw()).to_vec(),
+            wgpu::TextureFormat::Rgba16Float => to_f16_bytes(image.as_raw().iter().copied()),
+            _ => return Err(TextureError::Other(format!("HDR textures must be Rgba16Float or Rgba32Float, got {format:?}"))),
+        };
+
+        let tex = self.new_texture(size, format, false);
+        self.queue.write_texture(
+            tex.raw.as_image_copy(),
+            &data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size.x * format.target_pixel_byte_cost().unwrap_or(16)),
+                rows_per_image: None,
+            },
+            tex.raw.size(),
+        );
+        Ok(tex)
+    }
+
+    /// Copy mip 0 of the first layer of a texture back to the CPU, blocking until it arrives.
+    /// The texture needs `COPY_SRC` usage, rows are returned tightly packed
+    pub fn read_texture(&self, texture: &wgpu::Texture) -> Result<Vec<u8>, TextureError> {
+        let format = texture.format();
+        let texel_size = format.block_copy_size(None)
+            .ok_or_else(|| TextureError::Other(format!("can't read back {format:?} textures")))?;
+        let (width, height) = (texture.width(), texture.height());
+        let row_size = width * texel_size;
+        let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture readback"),
+            size: (padded_row_size * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::Wait)
+            .map_err(|e| TextureError::Other(format!("failed waiting for texture readback: {e}")))?;
+        receiver.recv()
+            .map_err(|_| TextureError::Other("texture readback was never mapped".into()))?
+            .map_err(|e| TextureError::Other(format!("failed to map texture readback: {e}")))?;
+
+        let mapped = slice.get_mapped_range();
+        let data = mapped.chunks(padded_row_size as usize)
+            .flat_map(|row| &row[..row_size as usize])
+            .copied()
+            .collect();
+        drop(mapped);
+        buffer.unmap();
+        Ok(data)
+    }
+
+    /// Save a float render target as an OpenEXR file for offline comparison
+    ///
+    /// Supports `R32Float`, `Rg32Float`, `Rgba32Float`, `R16Float`, `Rg16Float` and `Rgba16Float` textures with `COPY_SRC` usage
+    pub fn save_exr(&self, texture: &wgpu::Texture, path: &str) -> Result<(), TextureError> {
+        let format = texture.format();
+        let bytes = self.read_texture(texture)?;
+        let (channels, values): (usize, Vec<f32>) = match format {
+            wgpu::TextureFormat::R32Float    => (1, bytemuck::pod_collect_to_vec(&bytes)),
+            wgpu::TextureFormat::Rg32Float   => (2, bytemuck::pod_collect_to_vec(&bytes)),
+            wgpu::TextureFormat::Rgba32Float => (4, bytemuck::pod_collect_to_vec(&bytes)),
+            wgpu::TextureFormat::R16Float
+            | wgpu::TextureFormat::Rg16Float
+            | wgpu::TextureFormat::Rgba16Float => (
+                format.components() as usize,
+                bytemuck::pod_collect_to_vec::<u8, half::f16>(&bytes).into_iter().map(f32::from).collect(),
+            ),
+            _ => return Err(TextureError::Other(format!("EXR export needs a float texture, got {format:?}"))),
+        };
+
+        let rgba: Vec<f32> = values.chunks_exact(channels)
+            .flat_map(|texel| {
+                let mut out = [0.0, 0.0, 0.0, 1.0];
+                out[..channels].copy_from_slice(texel);
+                out
+            })
+            .collect();
+
+        let image = image::Rgba32FImage::from_raw(texture.width(), texture.height(), rgba)
+            .ok_or_else(|| TextureError::Other("texture readback returned the wrong amount of data".into()))?;
+        image.save_with_format(path, image::ImageFormat::OpenExr)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+use notify::Watcher;
+
+use crate::{bundle::ShaderBundle, diagnostics::{validate_wgsl, Diagnostic}, gpu::Gpu, preprocess::{normalize_path, preprocess_with_defs, DependencyGraph, PreprocessError, ShaderDefs}, resource::Generation, translate::{translate, ShaderLanguage}};
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Preprocess(PreprocessError),
+    /// naga rejected the preprocessed code
+    Invalid(Diagnostic),
+    /// wgpu rejected the module, holds its validation message
+    Compile(String),
+    Watch(notify::Error),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Preprocess(e) => write!(f, "{e}"),
+            ShaderError::Invalid(e) => write!(f, "{e}"),
+            ShaderError::Compile(e) => write!(f, "shader compilation failed: {e}"),
+            ShaderError::Watch(e) => write!(f, "failed to watch shader files: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl ShaderError {
+    /// The error as a diagnostic for display, errors that don't point at a line get one without labels
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            ShaderError::Preprocess(e) => e.into(),
+            ShaderError::Invalid(e) => e.clone(),
+            _ => Diagnostic { message: self.to_string(), labels: Vec::new(), notes: Vec::new() },
+        }
+    }
+}
+
+impl From<PreprocessError> for ShaderError {
+    fn from(e: PreprocessError) -> Self {
+        ShaderError::Preprocess(e)
+    }
+}
+
+impl From<notify::Error> for ShaderError {
+    fn from(e: notify::Error) -> Self {
+        ShaderError::Watch(e)
+    }
+}
+
+/// A WGSL file compiled with its imports, kept up to date by `ShaderWatcher`
+pub struct Shader {
+    pub path: String,
+    pub defs: ShaderDefs,
+    /// The last module that compiled, a failed reload leaves it in place
+    pub module: wgpu::ShaderModule,
+    pub graph: DependencyGraph,
+    pub compiled: SystemTime,
+    /// Why the latest reload failed, cleared by the next successful one
+    pub error: Option<Diagnostic>,
+    /// Bumped on every successful reload, see `HotPipeline`
+    pub generation: Generation,
+    files: HashSet<PathBuf>,
+}
+
+/// What a `ShaderWatcher::poll` call did
+#[derive(Debug, Default)]
+pub struct ShaderReloads {
+    /// Paths of shaders that recompiled, once per path
+    pub reloaded: Vec<String>,
+    /// Shaders that failed to recompile, by path. They keep their previous module and the diagnostic is also in `Shader::error`
+    pub failed: Vec<(String, ShaderError)>,
+    /// Errors from the file watcher, including directories of new imports that couldn't be watched
+    pub watch_errors: Vec<ShaderError>,
+}
+
+/// Watches shader files and their imports, recompiling shaders when any of them change
+///
+/// Native only, call `poll` once a frame before building pipelines
+pub struct ShaderWatcher {
+    watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    /// One entry per permutation, by path and defs
+    shaders: HashMap<(String, ShaderDefs), Shader>,
+    watched_dirs: HashSet<PathBuf>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Result<Self, ShaderError> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+
+        Ok(Self {
+            watcher,
+            events,
+            shaders: HashMap::new(),
+            watched_dirs: HashSet::new(),
+        })
+    }
+
+    /// Compile the shader file at `path` with `defs` and start watching it and everything it imports.
+    /// GLSL and SPIR-V files are translated to WGSL first, see `ShaderLanguage::from_path`.
+    /// Each set of defs is a separate permutation, loading one that is already watched recompiles it
+    pub fn load(&mut self, gpu: &Gpu, path: &str, defs: &ShaderDefs) -> Result<&Shader, ShaderError> {
+        let path = normalize_path(path);
+        let (graph, module) = compile(gpu, &path, defs)?;
+
+        let shader = Shader {
+            path: path.clone(),
+            defs: defs.clone(),
+            module,
+            files: absolute_files(&graph),
+            graph,
+            compiled: SystemTime::now(),
+            error: None,
+            generation: Generation::default(),
+        };
+        self.watch(&shader.files)?;
+        let key = (path, defs.clone());
+        self.shaders.insert(key.clone(), shader);
+        Ok(&self.shaders[&key])
+    }
+
+    /// Load a shader embedded with `include_wgsl_bundle!`. Debug builds read and watch its files like `load`,
+    /// release builds compile the embedded code and never reload it
+    pub fn load_bundle(&mut self, gpu: &Gpu, bundle: &ShaderBundle) -> Result<&Shader, ShaderError> {
+        if cfg!(debug_assertions) {
+            return self.load(gpu, bundle.path, &ShaderDefs::new());
+        }
+
+        let shader = Shader {
+            path: bundle.path.to_string(),
+            defs: ShaderDefs::new(),
+            module: gpu.device.create_shader_module(bundle.descriptor()),
+            graph: DependencyGraph {
+                root: bundle.path.to_string(),
+                imports: bundle.files.iter().map(|file| (file.to_string(), Vec::new())).collect(),
+            },
+            compiled: SystemTime::now(),
+            error: None,
+            generation: Generation::default(),
+            files: HashSet::new(),
+        };
+        let key = (shader.path.clone(), ShaderDefs::new());
+        self.shaders.insert(key.clone(), shader);
+        Ok(&self.shaders[&key])
+    }
+
+    pub fn get(&self, path: &str, defs: &ShaderDefs) -> Option<&Shader> {
+        self.shaders.get(&(normalize_path(path), defs.clone()))
+    }
+
+    /// Stop watching a permutation, its files stay watched if other shaders import them
+    pub fn remove(&mut self, path: &str, defs: &ShaderDefs) -> Option<Shader> {
+        self.shaders.remove(&(normalize_path(path), defs.clone()))
+    }
+
+    fn watch(&mut self, files: &HashSet<PathBuf>) -> Result<(), ShaderError> {
+        // watch directories rather than files, editors often save by replacing the file
+        for dir in files.iter().filter_map(|f| f.parent()) {
+            if !self.watched_dirs.contains(dir) {
+                self.watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+                self.watched_dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    /// Recompile shaders whose files changed since the last call and report which reloaded and which failed.
+    /// A failed reload leaves the shader's previous module in place
+    pub fn poll(&mut self, gpu: &Gpu) -> ShaderReloads {
+        let mut reloads = ShaderReloads::default();
+        let mut changed = HashSet::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if !event.kind.is_access() => changed.extend(event.paths),
+                Ok(_) => {},
+                Err(e) => reloads.watch_errors.push(e.into()),
+            }
+        }
+        if changed.is_empty() {
+            return reloads;
+        }
+
+        let mut new_files = HashSet::new();
+        for shader in self.shaders.values_mut() {
+            if shader.files.is_disjoint(&changed) {
+                continue;
+            }
+
+            match compile(gpu, &shader.path, &shader.defs) {
+                Ok((graph, module)) => {
+                    shader.module = module;
+                    shader.files = absolute_files(&graph);
+                    shader.graph = graph;
+                    shader.compiled = SystemTime::now();
+                    shader.error = None;
+                    shader.generation.bump();
+                    new_files.extend(shader.files.iter().cloned());
+                    reloads.reloaded.push(shader.path.clone());
+                },
+                Err(e) => {
+                    shader.error = Some(e.diagnostic());
+                    reloads.failed.push((shader.path.clone(), e));
+                },
+            }
+        }
+
+        reloads.reloaded.sort_unstable();
+        reloads.reloaded.dedup();
+
+        // a reload can add imports from directories that aren't watched yet
+        if let Err(e) = self.watch(&new_files) {
+            reloads.watch_errors.push(e);
+        }
+        reloads
+    }
+}
+
+fn compile(gpu: &Gpu, path: &str, defs: &ShaderDefs) -> Result<(DependencyGraph, wgpu::ShaderModule), ShaderError> {
+    let shader = match ShaderLanguage::from_path(path) {
+        ShaderLanguage::Wgsl => preprocess_with_defs(path, defs, |file| std::fs::read_to_string(file).map_err(|e| e.to_string()))?,
+        language => {
+            let bytes = std::fs::read(path)
+                .map_err(|e| PreprocessError::Load { path: path.to_string(), imported_from: None, reason: e.to_string() })?;
+            translate(path, language, &bytes, defs).map_err(ShaderError::Invalid)?
+        },
+    };
+    // naga errors can be mapped back through the imports, wgpu's can't
+    validate_wgsl(&shader).map_err(ShaderError::Invalid)?;
+
+    // capture the error here instead of letting it reach the uncaptured error handler
+    gpu.device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = gpu.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(path),
+        source: wgpu::ShaderSource::Wgsl(shader.code.into()),
+    });
+    if let Some(error) = pollster::block_on(gpu.device.pop_error_scope()) {
+        return Err(ShaderError::Compile(error.to_string()));
+    }
+
+    Ok((shader.graph, module))
+}
+
+fn absolute_files(graph: &DependencyGraph) -> HashSet<PathBuf> {
+    graph.files()
+        .map(|file| std::path::absolute(Path::new(file)).unwrap_or_else(|_| PathBuf::from(file)))
+        .collect()
+}
+
+/// A pipeline built from watched shaders, rebuilt the next time it's requested after any of them reloads
+pub struct HotPipeline<P> {
+    pipeline: Option<P>,
+    sources: Vec<(Generation, u64)>,
+}
+
+impl<P> Default for HotPipeline<P> {
+    fn default() -> Self {
+        Self {
+            pipeline: None,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl<P> HotPipeline<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a shader the pipeline was built from has reloaded since
+    pub fn is_stale(&self) -> bool {
+        self.sources.iter().any(|(generation, built)| generation.get() != *built)
+    }
+
+    /// The current pipeline, calling `build` first if there isn't one or one of `shaders` reloaded
+    pub fn get(&mut self, shaders: &[&Shader], build: impl FnOnce() -> P) -> &P {
+        let changed = self.sources.len() != shaders.len()
+            || shaders.iter().zip(&self.sources).any(|(s, (generation, _))| !s.generation.same_resource(generation));
+        if self.pipeline.is_none() || changed || self.is_stale() {
+            self.pipeline = Some(build());
+            self.sources = shaders.iter().map(|s| (s.generation.clone(), s.generation.get())).collect();
+        }
+        self.pipeline.as_ref().unwrap()
+    }
+}
@@ -1,11 +1,35 @@
+// lets `#[derive(BindGroup)]` output refer to `::hb_gpu` inside this crate too
+extern crate self as hb_gpu;
+
 pub mod texture;
 pub mod buffer;
 pub mod gpu;
 pub mod bindgroup;
+pub mod bindless;
 pub mod resource;
+pub mod cubemap;
+pub mod compressed;
+pub mod sampler;
+pub mod decode;
+pub mod atlas;
+pub mod texture_array;
+pub mod pool;
+pub mod hdr;
+pub mod blit;
+pub mod msaa;
+pub mod reflect;
+pub mod registry;
+pub use hb_gpu_shader::{codegen, diagnostics, preprocess};
+pub mod bundle;
+pub mod translate;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
 
 pub mod prelude {
-    pub use super::{bindgroup::*, buffer::*, gpu::*, resource::*, texture::*};
+    pub use super::{atlas::*, bindgroup::*, bindless::*, blit::*, bundle::*, buffer::*, codegen::*, cubemap::*, decode::*, diagnostics::*, gpu::*, hdr::*, pool::*, preprocess::*, reflect::*, registry::*, resource::*, sampler::*, texture::*, texture_array::*, translate::*};
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use super::hot_reload::*;
+    pub use bytemuck;
     pub use wgpu;
     pub use winit;
     pub use glam;
@@ -20,7 +44,6 @@ use winit::{
     dpi::PhysicalSize, window::Window, event_loop::ActiveEventLoop
 };
 
-// use std::time::SystemTime;
 
 #[cfg(target_arch = "wasm32")]
 use pollster::FutureExt;
@@ -68,19 +91,6 @@ pub fn new_window_in_canvas(event_loop: &ActiveEventLoop, canvas_id: &'static st
 }
 
 
-// pub struct Shader {
-//     path: String,
-//     deps: Vec<usize>,
-//     compiled: SystemTime,
-//     module: wgpu::ShaderModule,
-// }
-
-// impl Shader {
-//     pub fn new() -> Shader {
-//         wgpu::ShaderModule::
-//     }
-// }
-
 /// Fetch the bytes of a file. Returns None if an error occurred
 /// 
 /// # Panics
@@ -88,11 +98,7 @@ pub fn new_window_in_canvas(event_loop: &ActiveEventLoop, canvas_id: &'static st
 pub async fn fetch_bytes(path: &str) -> Option<Vec<u8>> {
     #[cfg(not(target_arch = "wasm32"))] 
     {
-        if let Ok(bytes) = std::fs::read(path) {
-            Some(bytes)
-        } else {
-            None
-        }
+        std::fs::read(path).ok()
 
     }
     
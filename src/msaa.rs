@@ -0,0 +1,67 @@
+use glam::UVec2;
+
+use crate::{gpu::Gpu, texture::{Texture, TextureError}};
+
+impl Gpu {
+    pub fn surface_size(&self) -> UVec2 {
+        UVec2::new(self.surface_config.width, self.surface_config.height)
+    }
+
+    /// Sample counts `format` can be rendered with on this device, always including 1
+    pub fn supported_sample_counts(&self, format: wgpu::TextureFormat) -> Vec<u32> {
+        // the device only allows the adapter's extra sample counts when adapter specific format features are enabled
+        let flags = if self.device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            self.adapter.get_texture_format_features(format).flags
+        } else {
+            format.guaranteed_format_features(self.device.features()).flags
+        };
+
+        let mut counts = flags.supported_sample_counts();
+        if !counts.contains(&1) {
+            counts.insert(0, 1);
+        }
+        counts
+    }
+
+    /// Create a multisampled color or depth target. Multisampled color textures can be bound with `BGBuilder::with_texture`
+    /// as `texture_multisampled_2d`, and rendered into with `TextureView::resolve_attachment`
+    pub fn new_msaa_texture(&self, size: UVec2, format: wgpu::TextureFormat, sample_count: u32) -> Result<Texture, TextureError> {
+        let supported = self.supported_sample_counts(format);
+        if !supported.contains(&sample_count) {
+            return Err(TextureError::Other(format!(
+                "{format:?} does not support {sample_count}x multisampling on this device, supported counts are {supported:?}"
+            )));
+        }
+
+        let desc = wgpu::TextureDescriptor {
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            label: None,
+            mip_level_count: 1,
+            sample_count,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+
+        Ok(self.new_texture_from_desc(&desc, wgpu::TextureViewDimension::D2))
+    }
+
+    /// Multisampled color target the size of the surface, matching the format of `get_surface_view`
+    pub fn new_msaa_surface_texture(&self, sample_count: u32) -> Result<Texture, TextureError> {
+        let format = self.surface_config.view_formats.iter().find(|f| f.is_srgb()).copied().unwrap_or(self.surface_config.format);
+        self.new_msaa_texture(self.surface_size(), format, sample_count)
+    }
+
+    /// Depth target the size of the surface, `sample_count` must match the color targets it is used with
+    pub fn new_depth_texture(&self, format: wgpu::TextureFormat, sample_count: u32) -> Result<Texture, TextureError> {
+        if !format.is_depth_stencil_format() {
+            return Err(TextureError::Other(format!("{format:?} is not a depth format")));
+        }
+        self.new_msaa_texture(self.surface_size(), format, sample_count)
+    }
+}
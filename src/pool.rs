@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use glam::UVec2;
+
+use crate::{gpu::Gpu, texture::Texture};
+
+/// Size of a pooled render target, either fixed or following the surface
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TargetSize {
+    Absolute(UVec2),
+    /// The surface size divided by this (1 for full resolution, 2 for half, ...), rounded up
+    Surface(u32),
+}
+
+impl TargetSize {
+    pub fn resolve(&self, surface_size: UVec2) -> UVec2 {
+        match *self {
+            TargetSize::Absolute(size) => size,
+            TargetSize::Surface(divisor) => {
+                let divisor = divisor.max(1);
+                UVec2::new(surface_size.x.div_ceil(divisor), surface_size.y.div_ceil(divisor)).max(UVec2::ONE)
+            },
+        }
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct TargetDesc {
+    pub size: TargetSize,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TargetDesc {
+    /// Single sampled, surface sized target that can be rendered to and sampled
+    pub fn surface(format: wgpu::TextureFormat) -> Self {
+        Self {
+            size: TargetSize::Surface(1),
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+
+    /// Surface sized multisampled target, resolve it with `TextureView::resolve_attachment`
+    pub fn msaa(format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        Self {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            sample_count,
+            ..Self::surface(format)
+        }
+    }
+}
+
+struct PoolEntry {
+    desc: TargetDesc,
+    size: UVec2,
+    texture: Arc<Texture>,
+    last_used: u64,
+    in_use: bool,
+}
+
+/// Hands out render targets by descriptor and recycles them across frames
+///
+/// Acquired targets stay reserved until `end_frame`, then become available again unless something still
+/// holds on to the `Arc`. Targets that go unused for `max_unused_frames` are freed, and surface sized targets
+/// are reallocated automatically when the surface is resized
+pub struct RenderTargetPool {
+    entries: Vec<PoolEntry>,
+    frame: u64,
+    pub max_unused_frames: u64,
+    surface_size: UVec2,
+}
+
+impl RenderTargetPool {
+    pub fn new(max_unused_frames: u64) -> Self {
+        Self {
+            entries: Vec::new(),
+            frame: 0,
+            max_unused_frames,
+            surface_size: UVec2::ZERO,
+        }
+    }
+
+    pub fn acquire(&mut self, gpu: &Gpu, desc: TargetDesc) -> Arc<Texture> {
+        let surface_size = gpu.surface_size();
+        if surface_size != self.surface_size {
+            // the old surface sized targets would never match again, so don't wait for them to expire
+            self.entries.retain(|e| !matches!(e.desc.size, TargetSize::Surface(_)) || Arc::strong_count(&e.texture) > 1);
+            self.surface_size = surface_size;
+        }
+
+        let size = desc.size.resolve(surface_size);
+        let frame = self.frame;
+        if let Some(entry) = self.entries.iter_mut()
+            .find(|e| e.desc == desc && e.size == size && !e.in_use && Arc::strong_count(&e.texture) == 1) {
+            entry.in_use = true;
+            entry.last_used = frame;
+            return entry.texture.clone();
+        }
+
+        let texture_desc = wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: desc.sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+            view_formats: &[],
+        };
+        let texture = Arc::new(gpu.new_texture_from_desc(&texture_desc, wgpu::TextureViewDimension::D2));
+
+        self.entries.push(PoolEntry {
+            desc,
+            size,
+            texture: texture.clone(),
+            last_used: frame,
+            in_use: true,
+        });
+        texture
+    }
+
+    /// Return every target acquired this frame to the pool and free the ones that have gone unused for too long
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        let max_unused = self.max_unused_frames;
+        self.entries.retain(|e| frame - e.last_used <= max_unused || Arc::strong_count(&e.texture) > 1);
+        for entry in &mut self.entries {
+            entry.in_use = false;
+        }
+        self.frame += 1;
+    }
+
+    /// Number of textures currently owned by the pool
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surface_size_rounds_up() {
+        let surface = UVec2::new(1921, 1080);
+        assert_eq!(TargetSize::Surface(1).resolve(surface), surface);
+        assert_eq!(TargetSize::Surface(2).resolve(surface), UVec2::new(961, 540));
+        assert_eq!(TargetSize::Surface(4).resolve(surface), UVec2::new(481, 270));
+        assert_eq!(TargetSize::Surface(3).resolve(UVec2::new(5, 7)), UVec2::new(2, 3));
+    }
+
+    #[test]
+    fn zero_divisor_means_full_size() {
+        let surface = UVec2::new(800, 600);
+        assert_eq!(TargetSize::Surface(0).resolve(surface), surface);
+    }
+
+    #[test]
+    fn resolved_size_is_at_least_one() {
+        assert_eq!(TargetSize::Surface(1).resolve(UVec2::ZERO), UVec2::ONE);
+        assert_eq!(TargetSize::Surface(8).resolve(UVec2::new(0, 16)), UVec2::new(1, 2));
+        assert_eq!(TargetSize::Absolute(UVec2::new(64, 32)).resolve(UVec2::ZERO), UVec2::new(64, 32));
+    }
+}
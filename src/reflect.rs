@@ -0,0 +1,693 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{bindgroup::{BindGroupError, BindGroupLayoutEntries}, gpu::Gpu, resource::ResourceManager};
+
+#[derive(Debug, Clone)]
+pub enum ReflectError {
+    /// The WGSL failed to parse, formatted with source snippets
+    Parse(String),
+    /// The module parsed but naga rejected it
+    Validation(String),
+    /// A resource that can't be described by a bind group layout entry
+    Unsupported { group: u32, binding: u32, reason: String },
+    /// Pipeline constants that don't fit the shader's `override` declarations
+    Constant { key: String, reason: String },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectError::Parse(e) => write!(f, "WGSL parse error: {e}"),
+            ReflectError::Validation(e) => write!(f, "WGSL validation error: {e}"),
+            ReflectError::Unsupported { group, binding, reason } => write!(f, "@group({group}) @binding({binding}): {reason}"),
+            ReflectError::Constant { key, reason } => write!(f, "override {key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+/// The bind group layouts a shader module declares with `@group` / `@binding`, keyed by group index
+///
+/// Visibility is the set of entry point stages that actually use each binding. Float textures are reflected as
+/// filterable and samplers as filtering, the same guess wgpu makes for pipelines without an explicit layout
+#[derive(Clone, Debug, Default)]
+pub struct ShaderLayout {
+    pub groups: BTreeMap<u32, BindGroupLayoutEntries>,
+    /// Pipeline-overridable constants, keyed the way `PipelineConstants` expects them
+    pub overrides: BTreeMap<String, OverrideConstant>,
+}
+
+/// An `override` declaration in a shader
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverrideConstant {
+    pub name: Option<String>,
+    /// The `@id` attribute, which replaces the name as the constant's key
+    pub id: Option<u16>,
+    /// WGSL scalar type, `bool`, `i32`, `u32`, `f32` or `f16`
+    pub ty: String,
+    /// Whether the declaration has an initializer, constants without one must be set when building a pipeline
+    pub has_default: bool,
+}
+
+impl OverrideConstant {
+    /// The key pipelines set this constant with: the `@id` as a decimal number, or the name
+    pub fn key(&self) -> String {
+        match (self.id, &self.name) {
+            (Some(id), _) => id.to_string(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Values for a shader's `override` constants, set when building a pipeline instead of making a copy of the shader
+/// for every variant. Pass `pairs()` as `wgpu::PipelineCompilationOptions::constants`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineConstants {
+    values: BTreeMap<String, f64>,
+}
+
+impl PipelineConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a constant by name, or by its `@id` written as a decimal number
+    pub fn set(&mut self, key: &str, value: impl Into<f64>) -> &mut Self {
+        self.values.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<f64> {
+        self.values.get(key).copied()
+    }
+
+    /// The values in the form `wgpu::PipelineCompilationOptions::constants` takes
+    pub fn pairs(&self) -> Vec<(&str, f64)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), *value)).collect()
+    }
+}
+
+impl ShaderLayout {
+    /// Parse and validate a WGSL module, then reflect its bindings
+    pub fn from_wgsl(source: &str) -> Result<Self, ReflectError> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|e| ReflectError::Parse(e.emit_to_string(source)))?;
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .map_err(|e| ReflectError::Validation(e.emit_to_string(source)))?;
+
+        Self::from_module(&module, &info)
+    }
+
+    pub fn from_module(module: &naga::Module, info: &naga::valid::ModuleInfo) -> Result<Self, ReflectError> {
+        let module_stages = module.entry_points.iter()
+            .fold(wgpu::ShaderStages::NONE, |stages, ep| stages | shader_stage(ep.stage));
+
+        let mut groups: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+        for (handle, var) in module.global_variables.iter() {
+            let Some(resource) = &var.binding else { continue };
+
+            let mut visibility = module.entry_points.iter().enumerate()
+                .filter(|(i, _)| !info.get_entry_point(*i)[handle].is_empty())
+                .fold(wgpu::ShaderStages::NONE, |stages, (_, ep)| stages | shader_stage(ep.stage));
+            // declared but never used, it still takes a slot in the layout
+            if visibility.is_empty() {
+                visibility = module_stages;
+            }
+
+            let (ty, count) = binding_type(module, var)
+                .map_err(|reason| ReflectError::Unsupported { group: resource.group, binding: resource.binding, reason })?;
+
+            groups.entry(resource.group).or_default().push(wgpu::BindGroupLayoutEntry {
+                binding: resource.binding,
+                visibility,
+                ty,
+                count,
+            });
+        }
+
+        let overrides = module.overrides.iter()
+            .map(|(_, o)| {
+                let constant = OverrideConstant {
+                    name: o.name.clone(),
+                    id: o.id,
+                    ty: scalar_name(&module.types[o.ty].inner),
+                    has_default: o.init.is_some(),
+                };
+                (constant.key(), constant)
+            })
+            .collect();
+
+        Ok(Self {
+            groups: groups.into_iter().map(|(group, entries)| (group, BindGroupLayoutEntries::new(entries))).collect(),
+            overrides,
+        })
+    }
+
+    /// Check that every constant is declared by the shader and fits its type, and that every `override` without a
+    /// default is set
+    pub fn check_constants(&self, constants: &PipelineConstants) -> Result<(), ReflectError> {
+        for (key, value) in &constants.values {
+            let Some(constant) = self.overrides.get(key) else {
+                return Err(ReflectError::Constant { key: key.clone(), reason: "the shader doesn't declare it".to_string() });
+            };
+            let fits = match constant.ty.as_str() {
+                "bool" => *value == 0.0 || *value == 1.0,
+                "i32" => value.fract() == 0.0 && *value >= i32::MIN as f64 && *value <= i32::MAX as f64,
+                "u32" => value.fract() == 0.0 && *value >= 0.0 && *value <= u32::MAX as f64,
+                _ => value.is_finite(),
+            };
+            if !fits {
+                return Err(ReflectError::Constant { key: key.clone(), reason: format!("{value} isn't a valid {}", constant.ty) });
+            }
+        }
+
+        match self.overrides.iter().find(|(key, c)| !c.has_default && constants.get(key).is_none()) {
+            Some((key, _)) => Err(ReflectError::Constant { key: key.clone(), reason: "has no default and wasn't set".to_string() }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn group(&self, group: u32) -> Option<&BindGroupLayoutEntries> {
+        self.groups.get(&group)
+    }
+
+    /// Compare `entries` against what the shader expects for `group`, see `BGBuilder::validate`
+    pub fn check(&self, group: u32, entries: &BindGroupLayoutEntries) -> Result<(), BindGroupError> {
+        let Some(expected) = self.group(group) else {
+            if entries.entries().is_empty() {
+                return Ok(());
+            }
+            return Err(BindGroupError::LayoutMismatch { group, problems: vec![format!("the shader has no bindings in group {group}")] });
+        };
+
+        let problems = compare_entries(expected, entries, false);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(BindGroupError::LayoutMismatch { group, problems })
+        }
+    }
+}
+
+/// Readable differences between two layouts, one line per binding
+///
+/// `exact` requires identical entries, as needed for a bind group to work with a pipeline layout built from
+/// `expected`. Otherwise filterability is ignored, visibility only has to cover `expected`, and extra bindings are fine
+pub(crate) fn compare_entries(expected: &BindGroupLayoutEntries, got: &BindGroupLayoutEntries, exact: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    for want in expected.entries() {
+        let binding = want.binding;
+        let expects = describe_binding(&want.ty, want.count);
+        let Some(entry) = got.get(binding) else {
+            problems.push(format!("binding {binding} expects {expects}, but nothing is bound"));
+            continue;
+        };
+
+        let type_matches = if exact { want.ty == entry.ty } else { compatible(&want.ty, &entry.ty) };
+        if !type_matches || want.count != entry.count {
+            problems.push(format!("binding {binding} expects {expects}, got {}", describe_binding(&entry.ty, entry.count)));
+        } else if exact && entry.visibility != want.visibility {
+            problems.push(format!("binding {binding} is declared for {:?} but visible to {:?}", want.visibility, entry.visibility));
+        } else if !entry.visibility.contains(want.visibility) {
+            problems.push(format!("binding {binding} is used by {:?} but only visible to {:?}", want.visibility, entry.visibility));
+        }
+    }
+
+    if exact {
+        for entry in got.entries().iter().filter(|e| expected.get(e.binding).is_none()) {
+            problems.push(format!("binding {} is bound to {} but not declared", entry.binding, describe_binding(&entry.ty, entry.count)));
+        }
+    }
+    problems
+}
+
+fn shader_stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+        naga::ShaderStage::Task => wgpu::ShaderStages::TASK,
+        naga::ShaderStage::Mesh => wgpu::ShaderStages::MESH,
+    }
+}
+
+fn binding_type(module: &naga::Module, var: &naga::GlobalVariable) -> Result<(wgpu::BindingType, Option<std::num::NonZeroU32>), String> {
+    let (ty, count) = match module.types[var.ty].inner {
+        naga::TypeInner::BindingArray { base, size } => match size {
+            naga::ArraySize::Constant(count) => (base, Some(count)),
+            _ => return Err("binding arrays need a constant size to build a layout".into()),
+        },
+        _ => (var.ty, None),
+    };
+
+    let binding = match (&module.types[ty].inner, var.space) {
+        (_, naga::AddressSpace::Uniform) => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        (_, naga::AddressSpace::Storage { access }) => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: !access.contains(naga::StorageAccess::STORE) },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        (naga::TypeInner::Sampler { comparison: true }, _) => wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+        (naga::TypeInner::Sampler { comparison: false }, _) => wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        (&naga::TypeInner::Image { dim, arrayed, class }, _) => {
+            let view_dimension = view_dimension(dim, arrayed)?;
+            match class {
+                naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        // wgpu rejects filterable multisampled bindings
+                        naga::ScalarKind::Float => wgpu::TextureSampleType::Float { filterable: !multi },
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        kind => return Err(format!("textures of {kind:?} can't be bound")),
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: storage_access(access),
+                    format: storage_format(format),
+                    view_dimension,
+                },
+            }
+        },
+        (naga::TypeInner::AccelerationStructure { .. }, _) => wgpu::BindingType::AccelerationStructure { vertex_return: false },
+        (inner, space) => return Err(format!("{inner:?} in the {space:?} address space isn't a bindable resource")),
+    };
+
+    Ok((binding, count))
+}
+
+fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> Result<wgpu::TextureViewDimension, String> {
+    Ok(match (dim, arrayed) {
+        (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+        (dim, true) => return Err(format!("{dim:?} textures can't be arrayed")),
+    })
+}
+
+fn storage_access(access: naga::StorageAccess) -> wgpu::StorageTextureAccess {
+    if access.contains(naga::StorageAccess::ATOMIC) {
+        wgpu::StorageTextureAccess::Atomic
+    } else if access.contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE) {
+        wgpu::StorageTextureAccess::ReadWrite
+    } else if access.contains(naga::StorageAccess::STORE) {
+        wgpu::StorageTextureAccess::WriteOnly
+    } else {
+        wgpu::StorageTextureAccess::ReadOnly
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+    match format {
+        S::R8Unorm => T::R8Unorm,
+        S::R8Snorm => T::R8Snorm,
+        S::R8Uint => T::R8Uint,
+        S::R8Sint => T::R8Sint,
+        S::R16Uint => T::R16Uint,
+        S::R16Sint => T::R16Sint,
+        S::R16Float => T::R16Float,
+        S::Rg8Unorm => T::Rg8Unorm,
+        S::Rg8Snorm => T::Rg8Snorm,
+        S::Rg8Uint => T::Rg8Uint,
+        S::Rg8Sint => T::Rg8Sint,
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg16Uint => T::Rg16Uint,
+        S::Rg16Sint => T::Rg16Sint,
+        S::Rg16Float => T::Rg16Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Bgra8Unorm => T::Bgra8Unorm,
+        S::Rgb10a2Uint => T::Rgb10a2Uint,
+        S::Rgb10a2Unorm => T::Rgb10a2Unorm,
+        S::Rg11b10Ufloat => T::Rg11b10Ufloat,
+        S::R64Uint => T::R64Uint,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+        S::R16Unorm => T::R16Unorm,
+        S::R16Snorm => T::R16Snorm,
+        S::Rg16Unorm => T::Rg16Unorm,
+        S::Rg16Snorm => T::Rg16Snorm,
+        S::Rgba16Unorm => T::Rgba16Unorm,
+        S::Rgba16Snorm => T::Rgba16Snorm,
+    }
+}
+
+/// Whether a bind group entry of type `got` can be used where the shader declares `want`
+fn compatible(want: &wgpu::BindingType, got: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType as B;
+    match (want, got) {
+        (B::Texture { sample_type: want_sample, view_dimension: want_dim, multisampled: want_multi },
+         B::Texture { sample_type: got_sample, view_dimension: got_dim, multisampled: got_multi }) => {
+            let sample_match = matches!(
+                (want_sample, got_sample),
+                (wgpu::TextureSampleType::Float { .. }, wgpu::TextureSampleType::Float { .. })
+            ) || want_sample == got_sample;
+            sample_match && want_dim == got_dim && want_multi == got_multi
+        },
+        (B::Sampler(want), B::Sampler(got)) => {
+            (*want == wgpu::SamplerBindingType::Comparison) == (*got == wgpu::SamplerBindingType::Comparison)
+        },
+        (B::Buffer { ty: want, .. }, B::Buffer { ty: got, .. }) => want == got,
+        (want, got) => want == got,
+    }
+}
+
+fn scalar_name(ty: &naga::TypeInner) -> String {
+    match ty {
+        naga::TypeInner::Scalar(scalar) => match (scalar.kind, scalar.width) {
+            (naga::ScalarKind::Bool, _) => "bool",
+            (naga::ScalarKind::Sint, _) => "i32",
+            (naga::ScalarKind::Uint, _) => "u32",
+            (naga::ScalarKind::Float, 2) => "f16",
+            (naga::ScalarKind::Float, _) => "f32",
+            _ => "?",
+        }.to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+/// Describe a binding the way WGSL spells it, like `texture_2d<f32>` or `read-only storage buffer`
+pub fn describe_binding(ty: &wgpu::BindingType, count: Option<std::num::NonZeroU32>) -> String {
+    let dim = |dim: &wgpu::TextureViewDimension| match dim {
+        wgpu::TextureViewDimension::D1 => "1d",
+        wgpu::TextureViewDimension::D2 => "2d",
+        wgpu::TextureViewDimension::D2Array => "2d_array",
+        wgpu::TextureViewDimension::Cube => "cube",
+        wgpu::TextureViewDimension::CubeArray => "cube_array",
+        wgpu::TextureViewDimension::D3 => "3d",
+    };
+
+    let inner = match ty {
+        wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. } => "uniform buffer".to_string(),
+        wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, .. } => "read-only storage buffer".to_string(),
+        wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, .. } => "storage buffer".to_string(),
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison) => "sampler_comparison".to_string(),
+        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering) => "sampler (non-filtering)".to_string(),
+        wgpu::BindingType::Sampler(_) => "sampler".to_string(),
+        wgpu::BindingType::Texture { sample_type, view_dimension, multisampled } => {
+            let multi = if *multisampled { "multisampled_" } else { "" };
+            match sample_type {
+                wgpu::TextureSampleType::Depth => format!("texture_depth_{multi}{}", dim(view_dimension)),
+                wgpu::TextureSampleType::Float { filterable: false } if !multisampled => format!("texture_{}<f32> (unfilterable)", dim(view_dimension)),
+                wgpu::TextureSampleType::Float { .. } => format!("texture_{multi}{}<f32>", dim(view_dimension)),
+                wgpu::TextureSampleType::Sint => format!("texture_{multi}{}<i32>", dim(view_dimension)),
+                wgpu::TextureSampleType::Uint => format!("texture_{multi}{}<u32>", dim(view_dimension)),
+            }
+        },
+        wgpu::BindingType::StorageTexture { access, format, view_dimension } => {
+            let access = match access {
+                wgpu::StorageTextureAccess::ReadOnly => "read",
+                wgpu::StorageTextureAccess::WriteOnly => "write",
+                wgpu::StorageTextureAccess::ReadWrite => "read_write",
+                wgpu::StorageTextureAccess::Atomic => "atomic",
+            };
+            format!("texture_storage_{}<{}, {access}>", dim(view_dimension), format!("{format:?}").to_lowercase())
+        },
+        other => format!("{other:?}"),
+    };
+
+    match count {
+        Some(count) => format!("binding_array<{inner}, {count}>"),
+        None => inner,
+    }
+}
+
+impl Gpu {
+    /// Create a pipeline layout from the bind group layouts a shader declares, sharing cached layouts with `BGBuilder`.
+    /// Groups the shader skips get an empty layout
+    pub fn new_pipeline_layout_from_shader(&self, resources: &mut ResourceManager, shader: &ShaderLayout) -> wgpu::PipelineLayout {
+        let group_count = shader.groups.keys().next_back().map_or(0, |g| g + 1);
+        let empty = BindGroupLayoutEntries::new(Vec::new());
+        let groups: Vec<&BindGroupLayoutEntries> = (0..group_count)
+            .map(|g| shader.group(g).unwrap_or(&empty))
+            .collect();
+
+        for entries in &groups {
+            resources.get_or_create_bind_group_layout(&self.device, entries, None);
+        }
+        let layouts: Vec<&wgpu::BindGroupLayout> = groups.iter()
+            .map(|entries| resources.get_bind_group_layout(entries).expect("layout was just created"))
+            .collect();
+
+        self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &layouts,
+            push_constant_ranges: &[],
+        })
+    }
+
+    /// Create a compute pipeline for one variant of a kernel, with the layout reflected from `shader` and its
+    /// `override` constants set from `constants`, which are checked against the shader first
+    pub fn new_compute_pipeline_from_shader(&self, resources: &mut ResourceManager, module: &wgpu::ShaderModule, shader: &ShaderLayout, entry_point: &str, constants: &PipelineConstants) -> Result<wgpu::ComputePipeline, ReflectError> {
+        shader.check_constants(constants)?;
+        let layout = self.new_pipeline_layout_from_shader(resources, shader);
+        let constants = constants.pairs();
+
+        Ok(self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&layout),
+            module,
+            entry_point: Some(entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+            cache: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINDINGS: &str = "
+struct Params { scale: f32 }
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> input: array<f32>;
+@group(0) @binding(2) var<storage, read_write> output: array<f32>;
+@group(0) @binding(3) var unused: texture_2d<f32>;
+@group(1) @binding(0) var color: texture_2d<f32>;
+@group(1) @binding(1) var color_sampler: sampler;
+@group(1) @binding(2) var shadow: texture_depth_2d;
+@group(1) @binding(3) var shadow_sampler: sampler_comparison;
+@group(1) @binding(4) var ms: texture_multisampled_2d<f32>;
+@group(1) @binding(5) var ids: texture_2d_array<u32>;
+@group(1) @binding(6) var image: texture_storage_2d<rgba8unorm, write>;
+@group(1) @binding(7) var textures: binding_array<texture_2d<f32>, 4>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(i) * params.scale, 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let c = textureSample(color, color_sampler, pos.xy);
+    let s = textureSampleCompare(shadow, shadow_sampler, pos.xy, 0.5);
+    let m = textureLoad(ms, vec2<i32>(0), 0);
+    let id = textureLoad(ids, vec2<i32>(0), 0, 0);
+    let t = textureLoad(textures[0], vec2<i32>(0), 0);
+    return c * s + m + t + vec4<f32>(f32(id.x) * params.scale);
+}
+
+@compute @workgroup_size(1)
+fn cs_main() {
+    output[0] = input[0] * params.scale;
+    textureStore(image, vec2<i32>(0), vec4<f32>(1.0));
+}
+";
+
+    fn entry(layout: &ShaderLayout, group: u32, binding: u32) -> (String, wgpu::ShaderStages) {
+        let entry = layout.group(group).unwrap().get(binding).unwrap();
+        (describe_binding(&entry.ty, entry.count), entry.visibility)
+    }
+
+    fn buffer(ty: wgpu::BufferBindingType) -> wgpu::BindingType {
+        wgpu::BindingType::Buffer { ty, has_dynamic_offset: false, min_binding_size: None }
+    }
+
+    fn layout_entry(binding: u32, visibility: wgpu::ShaderStages, ty: wgpu::BindingType) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry { binding, visibility, ty, count: None }
+    }
+
+    #[test]
+    fn reflects_every_binding_kind() {
+        use wgpu::ShaderStages as S;
+        let layout = ShaderLayout::from_wgsl(BINDINGS).unwrap();
+        assert_eq!(layout.groups.keys().copied().collect::<Vec<_>>(), [0, 1]);
+
+        assert_eq!(entry(&layout, 0, 0), ("uniform buffer".into(), S::VERTEX | S::FRAGMENT | S::COMPUTE));
+        assert_eq!(entry(&layout, 0, 1), ("read-only storage buffer".into(), S::COMPUTE));
+        assert_eq!(entry(&layout, 0, 2), ("storage buffer".into(), S::COMPUTE));
+        assert_eq!(entry(&layout, 1, 0), ("texture_2d<f32>".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 1), ("sampler".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 2), ("texture_depth_2d".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 3), ("sampler_comparison".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 4), ("texture_multisampled_2d<f32>".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 5), ("texture_2d_array<u32>".into(), S::FRAGMENT));
+        assert_eq!(entry(&layout, 1, 6), ("texture_storage_2d<rgba8unorm, write>".into(), S::COMPUTE));
+        assert_eq!(entry(&layout, 1, 7), ("binding_array<texture_2d<f32>, 4>".into(), S::FRAGMENT));
+
+        let ms = layout.group(1).unwrap().get(4).unwrap();
+        assert!(matches!(ms.ty, wgpu::BindingType::Texture { sample_type: wgpu::TextureSampleType::Float { filterable: false }, .. }));
+    }
+
+    #[test]
+    fn unused_bindings_are_visible_to_every_stage() {
+        use wgpu::ShaderStages as S;
+        let layout = ShaderLayout::from_wgsl(BINDINGS).unwrap();
+        assert_eq!(entry(&layout, 0, 3).1, S::VERTEX | S::FRAGMENT | S::COMPUTE);
+    }
+
+    #[test]
+    fn mismatches_are_described_per_binding() {
+        use wgpu::ShaderStages as S;
+        let layout = ShaderLayout::from_wgsl("
+@group(0) @binding(0) var<uniform> scale: f32;
+@group(0) @binding(1) var color_sampler: sampler;
+@group(0) @binding(2) var color: texture_2d<f32>;
+
+@fragment
+fn main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureSample(color, color_sampler, pos.xy) * scale;
+}
+").unwrap();
+
+        let texture = wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let sampler = wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering);
+
+        // filterability is a guess, so an unfilterable texture and a non-filtering sampler still match
+        let matching = BindGroupLayoutEntries::new(vec![
+            layout_entry(0, S::VERTEX_FRAGMENT, buffer(wgpu::BufferBindingType::Uniform)),
+            layout_entry(1, S::FRAGMENT, sampler),
+            layout_entry(2, S::FRAGMENT, texture),
+            layout_entry(3, S::FRAGMENT, buffer(wgpu::BufferBindingType::Uniform)),
+        ]);
+        assert!(layout.check(0, &matching).is_ok());
+
+        let wrong = BindGroupLayoutEntries::new(vec![
+            layout_entry(0, S::VERTEX, buffer(wgpu::BufferBindingType::Uniform)),
+            layout_entry(2, S::FRAGMENT, buffer(wgpu::BufferBindingType::Storage { read_only: false })),
+        ]);
+        let Err(BindGroupError::LayoutMismatch { group, problems }) = layout.check(0, &wrong) else {
+            panic!("expected a layout mismatch");
+        };
+        assert_eq!(group, 0);
+        assert_eq!(problems, [
+            "binding 0 is used by ShaderStages(FRAGMENT) but only visible to ShaderStages(VERTEX)",
+            "binding 1 expects sampler, but nothing is bound",
+            "binding 2 expects texture_2d<f32>, got storage buffer",
+        ]);
+
+        let Err(BindGroupError::LayoutMismatch { problems, .. }) = layout.check(1, &matching) else {
+            panic!("expected a layout mismatch");
+        };
+        assert_eq!(problems, ["the shader has no bindings in group 1"]);
+    }
+
+    #[test]
+    fn exact_comparison_reports_extra_bindings_and_filterability() {
+        use wgpu::ShaderStages as S;
+        let expected = BindGroupLayoutEntries::new(vec![
+            layout_entry(0, S::FRAGMENT, wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
+        ]);
+        let got = BindGroupLayoutEntries::new(vec![
+            layout_entry(0, S::FRAGMENT, wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering)),
+            layout_entry(1, S::FRAGMENT, buffer(wgpu::BufferBindingType::Storage { read_only: true })),
+        ]);
+        assert_eq!(compare_entries(&expected, &got, false), Vec::<String>::new());
+        assert_eq!(compare_entries(&expected, &got, true), [
+            "binding 0 expects sampler, got sampler (non-filtering)",
+            "binding 1 is bound to read-only storage buffer but not declared",
+        ]);
+    }
+
+    #[test]
+    fn override_constants_are_checked() {
+        let layout = ShaderLayout::from_wgsl("
+@id(7) override block_size: u32 = 64u;
+override use_fast_path: bool = true;
+override offset: i32;
+override strength: f32 = 1.0;
+
+@compute @workgroup_size(1)
+fn main() {
+    _ = block_size;
+    _ = use_fast_path;
+    _ = offset;
+    _ = strength;
+}
+").unwrap();
+        assert_eq!(layout.overrides.keys().cloned().collect::<Vec<_>>(), ["7", "offset", "strength", "use_fast_path"]);
+        assert_eq!(layout.overrides["7"], OverrideConstant { name: Some("block_size".into()), id: Some(7), ty: "u32".into(), has_default: true });
+        assert!(!layout.overrides["offset"].has_default);
+
+        let mut constants = PipelineConstants::new();
+        constants.set("7", 128).set("offset", -3).set("use_fast_path", 0).set("strength", 0.5);
+        assert!(layout.check_constants(&constants).is_ok());
+
+        let reason = |constants: &PipelineConstants| match layout.check_constants(constants) {
+            Err(ReflectError::Constant { key, reason }) => format!("{key}: {reason}"),
+            other => panic!("expected a constant error, got {other:?}"),
+        };
+
+        let mut missing = PipelineConstants::new();
+        missing.set("7", 32);
+        assert_eq!(reason(&missing), "offset: has no default and wasn't set");
+
+        let mut unknown = constants.clone();
+        unknown.set("block_size", 32);
+        assert_eq!(reason(&unknown), "block_size: the shader doesn't declare it");
+
+        let mut negative = constants.clone();
+        negative.set("7", -1);
+        assert_eq!(reason(&negative), "7: -1 isn't a valid u32");
+
+        let mut fractional = constants.clone();
+        fractional.set("offset", 1.5);
+        assert_eq!(reason(&fractional), "offset: 1.5 isn't a valid i32");
+
+        let mut not_bool = constants.clone();
+        not_bool.set("use_fast_path", 2);
+        assert_eq!(reason(&not_bool), "use_fast_path: 2 isn't a valid bool");
+
+        let mut infinite = constants;
+        infinite.set("strength", f64::INFINITY);
+        assert_eq!(reason(&infinite), "strength: inf isn't a valid f32");
+    }
+}
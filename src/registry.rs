@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use crate::{bindgroup::BindGroup, buffer::Buffer, sampler::Sampler, texture::Texture};
+
+/// Typed index into a `ResourceManager`, cheap to copy and store in app structs instead of references
+///
+/// Handles carry the generation of their slot, so a handle to a destroyed resource never resolves to whatever
+/// reuses the slot later
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = std::any::type_name::<T>().rsplit("::").next().unwrap_or("?");
+        write!(f, "Handle<{name}>({}v{})", self.index, self.generation)
+    }
+}
+
+struct Entry<T> {
+    value: T,
+    label: Option<String>,
+    ref_count: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<Entry<T>>,
+}
+
+/// Storage for one resource type, slots are reused through a free list with their generation bumped
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    names: HashMap<String, Handle<T>>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+/// One live resource as seen by `ResourceManager::iter`
+pub struct RegistryEntry<'a, T> {
+    pub handle: Handle<T>,
+    pub label: Option<&'a str>,
+    pub ref_count: u32,
+    pub value: &'a T,
+}
+
+impl<T> Pool<T> {
+    pub(crate) fn insert(&mut self, value: T, label: Option<&str>) -> Handle<T> {
+        let entry = Entry {
+            value,
+            label: label.map(str::to_string),
+            ref_count: 1,
+        };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].entry = Some(entry);
+                index
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, entry: Some(entry) });
+                self.slots.len() as u32 - 1
+            },
+        };
+
+        let handle = Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            _marker: PhantomData,
+        };
+        if let Some(label) = label {
+            self.names.insert(label.to_string(), handle);
+        }
+        handle
+    }
+
+    fn entry(&self, handle: Handle<T>) -> Option<&Entry<T>> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entry.as_ref()
+    }
+
+    fn entry_mut(&mut self, handle: Handle<T>) -> Option<&mut Entry<T>> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.entry.as_mut()
+    }
+
+    pub(crate) fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.entry(handle).map(|e| &e.value)
+    }
+
+    pub(crate) fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.entry_mut(handle).map(|e| &mut e.value)
+    }
+
+    pub(crate) fn label(&self, handle: Handle<T>) -> Option<&str> {
+        self.entry(handle)?.label.as_deref()
+    }
+
+    pub(crate) fn ref_count(&self, handle: Handle<T>) -> u32 {
+        self.entry(handle).map_or(0, |e| e.ref_count)
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).copied().filter(|&h| self.entry(h).is_some())
+    }
+
+    pub(crate) fn retain(&mut self, handle: Handle<T>) -> bool {
+        match self.entry_mut(handle) {
+            Some(entry) => {
+                entry.ref_count += 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Drop one reference, returning the value once the last one is gone
+    pub(crate) fn release(&mut self, handle: Handle<T>) -> Option<T> {
+        let entry = self.entry_mut(handle)?;
+        entry.ref_count -= 1;
+        if entry.ref_count > 0 {
+            return None;
+        }
+        self.remove(handle)
+    }
+
+    pub(crate) fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        self.entry(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        let entry = slot.entry.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+
+        if let Some(label) = &entry.label
+            && self.names.get(label) == Some(&handle) {
+            self.names.remove(label);
+        }
+        Some(entry.value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = RegistryEntry<'_, T>> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let entry = slot.entry.as_ref()?;
+            Some(RegistryEntry {
+                handle: Handle { index: index as u32, generation: slot.generation, _marker: PhantomData },
+                label: entry.label.as_deref(),
+                ref_count: entry.ref_count,
+                value: &entry.value,
+            })
+        })
+    }
+}
+
+/// The pools behind `ResourceManager`'s handle API, one per resource type
+#[derive(Default)]
+pub struct Registry {
+    buffers: Pool<Buffer>,
+    textures: Pool<Texture>,
+    samplers: Pool<Sampler>,
+    shaders: Pool<wgpu::ShaderModule>,
+    render_pipelines: Pool<wgpu::RenderPipeline>,
+    compute_pipelines: Pool<wgpu::ComputePipeline>,
+    bind_groups: Pool<BindGroup>,
+}
+
+/// A type `ResourceManager` can hold, mapping it to its pool in the `Registry`
+pub trait Registered: Sized + 'static {
+    fn pool(registry: &Registry) -> &Pool<Self>;
+    fn pool_mut(registry: &mut Registry) -> &mut Pool<Self>;
+
+    /// Free GPU memory right away when the resource is destroyed, instead of when the last wgpu reference drops.
+    /// Buffers and textures also bump their `Generation` so anything built from them reports itself stale
+    fn destroy(&self) {}
+}
+
+macro_rules! registered {
+    ($ty:ty, $field:ident) => {
+        impl Registered for $ty {
+            fn pool(registry: &Registry) -> &Pool<Self> {
+                &registry.$field
+            }
+
+            fn pool_mut(registry: &mut Registry) -> &mut Pool<Self> {
+                &mut registry.$field
+            }
+        }
+    };
+    ($ty:ty, $field:ident, destroy) => {
+        impl Registered for $ty {
+            fn pool(registry: &Registry) -> &Pool<Self> {
+                &registry.$field
+            }
+
+            fn pool_mut(registry: &mut Registry) -> &mut Pool<Self> {
+                &mut registry.$field
+            }
+
+            fn destroy(&self) {
+                // bind groups and bindless tables holding it see the bump and rebuild instead of using freed memory
+                self.generation.bump();
+                self.raw.destroy();
+            }
+        }
+    };
+}
+
+registered!(Buffer, buffers, destroy);
+registered!(Texture, textures, destroy);
+registered!(Sampler, samplers);
+registered!(wgpu::ShaderModule, shaders);
+registered!(wgpu::RenderPipeline, render_pipelines);
+registered!(wgpu::ComputePipeline, compute_pipelines);
+registered!(BindGroup, bind_groups);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slots_bump_the_generation() {
+        let mut pool = Pool::default();
+        let first = pool.insert("first", None);
+        assert_eq!(pool.remove(first), Some("first"));
+
+        let second = pool.insert("second", None);
+        assert_eq!(second.index(), first.index());
+        assert_eq!(second.generation(), first.generation() + 1);
+        assert_ne!(second, first);
+
+        // the stale handle never sees the new value
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.get_mut(first), None);
+        assert_eq!(pool.remove(first), None);
+        assert!(!pool.retain(first));
+        assert_eq!(pool.get(second), Some(&"second"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn release_removes_after_the_last_reference() {
+        let mut pool = Pool::default();
+        let handle = pool.insert(7, None);
+        assert!(pool.retain(handle));
+        assert_eq!(pool.ref_count(handle), 2);
+
+        assert_eq!(pool.release(handle), None);
+        assert_eq!(pool.get(handle), Some(&7));
+        assert_eq!(pool.release(handle), Some(7));
+        assert_eq!(pool.get(handle), None);
+        assert_eq!(pool.ref_count(handle), 0);
+        assert_eq!(pool.release(handle), None);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn names_follow_the_live_resource() {
+        let mut pool = Pool::default();
+        let old = pool.insert(1, Some("shadow map"));
+        let new = pool.insert(2, Some("shadow map"));
+        assert_eq!(pool.find("shadow map"), Some(new));
+
+        // removing the older resource doesn't unregister the name of the newer one
+        pool.remove(old);
+        assert_eq!(pool.find("shadow map"), Some(new));
+        assert_eq!(pool.label(new), Some("shadow map"));
+
+        pool.remove(new);
+        assert_eq!(pool.find("shadow map"), None);
+        let reused = pool.insert(3, None);
+        assert_eq!(pool.find("shadow map"), None);
+        assert_eq!(pool.label(reused), None);
+    }
+
+    #[test]
+    fn iter_skips_free_slots() {
+        let mut pool = Pool::default();
+        let handles: Vec<_> = (0..4).map(|i| pool.insert(i, None)).collect();
+        pool.remove(handles[1]);
+        pool.remove(handles[2]);
+        let reused = pool.insert(9, Some("reused"));
+
+        let live: Vec<_> = pool.iter().map(|e| (e.handle, *e.value, e.label)).collect();
+        assert_eq!(live, [(handles[0], 0, None), (reused, 9, Some("reused")), (handles[3], 3, None)]);
+    }
+}
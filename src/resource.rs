@@ -1,15 +1,56 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::bindgroup::BindGroupLayoutEntries;
+use crate::bindgroup::{BindGroup, BindGroupError, BindGroupLayoutEntries};
+use crate::diagnostics::Diagnostic;
+use crate::fetch_bytes;
+use crate::preprocess::{find_imports, normalize_path, preprocess_with_defs, PreprocessError, PreprocessedShader, ShaderDefs};
+use crate::reflect::{ReflectError, ShaderLayout};
+use crate::registry::{Handle, Registered, Registry, RegistryEntry};
+use crate::sampler::{Sampler, SamplerDesc};
+use crate::translate::{translate, ShaderLanguage};
 
+/// Counter shared by a resource and everything built from it, bumped whenever the resource's GPU object is replaced
+///
+/// Clones share the same counter, so a `BindGroup` can hold one and notice the resource changed without borrowing it
+#[derive(Clone, Default, Debug)]
+pub struct Generation(Arc<AtomicU64>);
+
+impl Generation {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Whether two handles count the same resource
+    pub fn same_resource(&self, other: &Generation) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
 
 /// Caches bind group layouts in probably the least efficient way possible
 /// 
 /// Not sure why I made this, thought I would be recreating bind groups a lot more often
+/// 
+/// Also deduplicates samplers, see `Gpu::new_sampler`, and holds buffers, textures, samplers, shader modules, pipelines
+/// and bind groups behind typed `Handle`s with `add` / `get` / `destroy`
 #[derive(Default)]
 pub struct ResourceManager {
     pub bind_group_layouts: HashMap<BindGroupLayoutEntries, wgpu::BindGroupLayout>,
-    // shaders: Vec<Shader>,
+    pub samplers: HashMap<SamplerDesc, Sampler>,
+    /// Layouts reflected from WGSL with `reflect_wgsl`, by name
+    pub shader_layouts: HashMap<String, ShaderLayout>,
+    /// Bind groups kept up to date by `cached_bind_group`, by name
+    pub bind_groups: HashMap<String, BindGroup>,
+    /// Preprocessed WGSL with imports inlined, or GLSL and SPIR-V translated to WGSL, by root path and defs
+    pub shader_sources: HashMap<(String, ShaderDefs), PreprocessedShader>,
+    /// Modules created from `shader_sources` by `get_or_create_shader_module`
+    pub shader_modules: HashMap<(String, ShaderDefs), wgpu::ShaderModule>,
+    registry: Registry,
 }
 
 impl ResourceManager {
@@ -21,34 +62,216 @@ impl ResourceManager {
         self.bind_group_layouts.get(layout_entries)
     }
 
-    // async fn get_shader(&mut self, path: String) -> Option<usize> {
-    //     if let Some(i) = self.shaders.iter().position(|s| { s.path == path }) {
-    //         return Some(i);
-    //     }
+    /// Get the cached layout for these entries, creating it with `label` if this is the first time they're seen
+    pub fn get_or_create_bind_group_layout(&mut self, device: &wgpu::Device, layout_entries: &BindGroupLayoutEntries, label: Option<&str>) -> &wgpu::BindGroupLayout {
+        if !self.bind_group_layouts.contains_key(layout_entries) {
+            let layout_desc = wgpu::BindGroupLayoutDescriptor {
+                label,
+                entries: layout_entries.entries(),
+            };
+            self.bind_group_layouts.insert(layout_entries.clone(), device.create_bind_group_layout(&layout_desc));
+        }
+
+        self.get_bind_group_layout(layout_entries).expect("hash get failed after insertion")
+    }
+
+    /// Parse a WGSL module and store the bind group layouts it declares under `name`, replacing any previous ones
+    pub fn reflect_wgsl(&mut self, name: &str, source: &str) -> Result<&ShaderLayout, ReflectError> {
+        let layout = ShaderLayout::from_wgsl(source)?;
+        self.shader_layouts.insert(name.to_string(), layout);
+        Ok(&self.shader_layouts[name])
+    }
+
+    /// Get the bind group cached under `name`, calling `build` to create it the first time and
+    /// again whenever one of the resources it was built from has been resized, grown or destroyed
+    /// `build` usually just runs a `BGBuilder` with the current resources and finishes it with the manager it's given
+    pub fn cached_bind_group(&mut self, name: &str, build: impl FnOnce(&mut ResourceManager) -> Result<BindGroup, BindGroupError>) -> Result<&BindGroup, BindGroupError> {
+        let fresh = self.bind_groups.get(name).is_some_and(|group| !group.is_stale());
+        if !fresh {
+            let group = build(self)?;
+            self.bind_groups.insert(name.to_string(), group);
+        }
+        Ok(&self.bind_groups[name])
+    }
+
+    pub fn get_shader_layout(&self, name: &str) -> Option<&ShaderLayout> {
+        self.shader_layouts.get(name)
+    }
+
+    /// Take ownership of a resource and return a handle to it with one reference.
+    /// A label also registers the handle for `find`, replacing any earlier resource of that type with the same label
+    pub fn add<T: Registered>(&mut self, value: T, label: Option<&str>) -> Handle<T> {
+        T::pool_mut(&mut self.registry).insert(value, label)
+    }
+
+    /// `None` once the resource has been destroyed
+    pub fn get<T: Registered>(&self, handle: Handle<T>) -> Option<&T> {
+        T::pool(&self.registry).get(handle)
+    }
+
+    pub fn get_mut<T: Registered>(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        T::pool_mut(&mut self.registry).get_mut(handle)
+    }
+
+    /// Look up a resource by the label it was added with
+    pub fn find<T: Registered>(&self, label: &str) -> Option<Handle<T>> {
+        T::pool(&self.registry).find(label)
+    }
+
+    pub fn label<T: Registered>(&self, handle: Handle<T>) -> Option<&str> {
+        T::pool(&self.registry).label(handle)
+    }
+
+    /// Number of references to a resource, 0 if it has been destroyed
+    pub fn ref_count<T: Registered>(&self, handle: Handle<T>) -> u32 {
+        T::pool(&self.registry).ref_count(handle)
+    }
+
+    /// Add a reference, returns false if the resource was already destroyed
+    pub fn retain<T: Registered>(&mut self, handle: Handle<T>) -> bool {
+        T::pool_mut(&mut self.registry).retain(handle)
+    }
+
+    /// Drop a reference, destroying the resource when it was the last one. Returns true if it was destroyed
+    pub fn release<T: Registered>(&mut self, handle: Handle<T>) -> bool {
+        match T::pool_mut(&mut self.registry).release(handle) {
+            Some(value) => {
+                value.destroy();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Destroy a resource now regardless of its reference count. Buffers and textures free their GPU memory
+    /// immediately and bump their generation, so bind groups built from them report `is_stale`
+    pub fn destroy<T: Registered>(&mut self, handle: Handle<T>) -> bool {
+        match T::pool_mut(&mut self.registry).remove(handle) {
+            Some(value) => {
+                value.destroy();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Every live resource of one type with its handle, label and reference count, for inspection tools
+    pub fn iter<T: Registered>(&self) -> impl Iterator<Item = RegistryEntry<'_, T>> {
+        T::pool(&self.registry).iter()
+    }
+
+    /// Number of live resources of one type
+    pub fn count<T: Registered>(&self) -> usize {
+        T::pool(&self.registry).len()
+    }
+
+    /// Preprocess the WGSL file at `root` with `defs`, caching the result by (path, defs) so each permutation is only
+    /// built once. `load` reads a file and returns its contents, see `preprocess::preprocess_with_defs`
+    pub fn preprocess_wgsl(&mut self, root: &str, defs: &ShaderDefs, load: impl FnMut(&str) -> Result<String, String>) -> Result<&PreprocessedShader, PreprocessError> {
+        let key = (normalize_path(root), defs.clone());
+        if !self.shader_sources.contains_key(&key) {
+            let shader = preprocess_with_defs(root, defs, load)?;
+            self.shader_sources.insert(key.clone(), shader);
+        }
+        Ok(&self.shader_sources[&key])
+    }
+
+    /// Like `preprocess_wgsl`, loading every file with `fetch_bytes` so imports also work on wasm
+    pub async fn load_wgsl(&mut self, root: &str, defs: &ShaderDefs) -> Result<&PreprocessedShader, PreprocessError> {
+        let key = (normalize_path(root), defs.clone());
+        if self.shader_sources.contains_key(&key) {
+            return Ok(&self.shader_sources[&key]);
+        }
 
-    //     let Some(bytes) = fetch_bytes(&path).await else {
-    //         return None;
-    //     };
-    //     let Ok(contents) = str::from_utf8(bytes.as_slice()) else {
-    //         return None;
-    //     };
+        // fetch everything reachable up front, the preprocessor itself is synchronous.
+        // This includes imports in blocks `defs` turns off, they're only reported if actually needed
+        let mut files: HashMap<String, Result<String, String>> = HashMap::new();
+        let mut queue = vec![key.0];
+        while let Some(path) = queue.pop() {
+            if files.contains_key(&path) {
+                continue;
+            }
+            let source = match fetch_bytes(&path).await {
+                Some(bytes) => String::from_utf8(bytes).map_err(|_| "file isn't valid UTF-8".to_string()),
+                None => Err("file not found".to_string()),
+            };
+            if let Ok(source) = &source
+                && let Ok(imports) = find_imports(&path, source) {
+                queue.extend(imports);
+            }
+            files.insert(path, source);
+        }
 
-    //     for line in contents.lines() {
-    //         if let Some(rel) = line.trim().strip_prefix("@import ").and_then(|s| {
-    //             s.trim().strip_prefix('"')?.strip_suffix('"')
-    //         }) {
-                
-    //         }
-    //     }
+        self.preprocess_wgsl(root, defs, |path| files.get(path).cloned().unwrap_or_else(|| Err("file not found".to_string())))
+    }
 
-    //     Some(self.shaders.len() - 1)
-    // }
+    /// Like `preprocess_wgsl` for a shader in any `ShaderLanguage`, picked by its extension. GLSL and SPIR-V are
+    /// translated to WGSL and cached the same way, so the rest of the shader functions don't care what it was written in
+    pub fn preprocess_shader(&mut self, root: &str, defs: &ShaderDefs, mut load: impl FnMut(&str) -> Result<Vec<u8>, String>) -> Result<&PreprocessedShader, Diagnostic> {
+        let language = ShaderLanguage::from_path(root);
+        if language == ShaderLanguage::Wgsl {
+            let load = |path: &str| load(path).and_then(|bytes| String::from_utf8(bytes).map_err(|_| "file isn't valid UTF-8".to_string()));
+            return self.preprocess_wgsl(root, defs, load).map_err(|e| Diagnostic::from(&e));
+        }
 
-    // pub async fn add_shader(path: String) -> bool {
+        let key = (normalize_path(root), defs.clone());
+        if !self.shader_sources.contains_key(&key) {
+            let bytes = load(&key.0).map_err(|reason| Diagnostic::from(&PreprocessError::Load { path: key.0.clone(), imported_from: None, reason }))?;
+            let shader = translate(&key.0, language, &bytes, defs)?;
+            self.shader_sources.insert(key.clone(), shader);
+        }
+        Ok(&self.shader_sources[&key])
+    }
 
-        
+    /// Like `preprocess_shader`, loading with `fetch_bytes`
+    pub async fn load_shader(&mut self, root: &str, defs: &ShaderDefs) -> Result<&PreprocessedShader, Diagnostic> {
+        if ShaderLanguage::from_path(root) == ShaderLanguage::Wgsl {
+            return self.load_wgsl(root, defs).await.map_err(|e| Diagnostic::from(&e));
+        }
 
-    //     true
-    // }
+        let key = (normalize_path(root), defs.clone());
+        if !self.shader_sources.contains_key(&key) {
+            let bytes = fetch_bytes(&key.0).await.ok_or_else(|| "file not found".to_string());
+            return self.preprocess_shader(root, defs, |_| bytes.clone());
+        }
+        Ok(&self.shader_sources[&key])
+    }
+
+    /// A permutation cached by `preprocess_wgsl`, `preprocess_shader` or their async versions
+    pub fn get_shader_source(&self, root: &str, defs: &ShaderDefs) -> Option<&PreprocessedShader> {
+        self.shader_sources.get(&(normalize_path(root), defs.clone()))
+    }
 
-}
\ No newline at end of file
+    /// The shader module for a cached permutation, created the first time it's asked for.
+    /// `None` if the permutation hasn't been preprocessed yet
+    pub fn get_or_create_shader_module(&mut self, device: &wgpu::Device, root: &str, defs: &ShaderDefs) -> Option<&wgpu::ShaderModule> {
+        let key = (normalize_path(root), defs.clone());
+        if !self.shader_modules.contains_key(&key) {
+            let source = self.shader_sources.get(&key)?;
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&key.0),
+                source: wgpu::ShaderSource::Wgsl(source.code.as_str().into()),
+            });
+            self.shader_modules.insert(key.clone(), module);
+        }
+        self.shader_modules.get(&key)
+    }
+
+    /// Root shaders that include `path`, directly or through other imports
+    pub fn shaders_importing(&self, path: &str) -> Vec<&str> {
+        let mut roots: Vec<&str> = self.shader_sources.values()
+            .filter(|shader| shader.graph.contains(path))
+            .map(|shader| shader.graph.root.as_str())
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        roots
+    }
+
+    /// Drop every cached permutation and module that includes `path`, so the next load reads the files again
+    pub fn invalidate_shader(&mut self, path: &str) {
+        self.shader_sources.retain(|_, shader| !shader.graph.contains(path));
+        let sources = &self.shader_sources;
+        self.shader_modules.retain(|key, _| sources.contains_key(key));
+    }
+}
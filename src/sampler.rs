@@ -0,0 +1,175 @@
+use crate::{gpu::Gpu, resource::ResourceManager};
+
+/// Hashable subset of `wgpu::SamplerDescriptor`, used as the key for sampler deduplication
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub address_mode: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// 1 disables anisotropic filtering, anything higher requires all filters to be linear
+    pub anisotropy_clamp: u16,
+    /// Makes this a comparison sampler, for shadow maps
+    pub compare: Option<wgpu::CompareFunction>,
+}
+
+#[derive(Clone)]
+pub struct Sampler {
+    pub raw: wgpu::Sampler,
+    pub desc: SamplerDesc,
+}
+
+impl SamplerDesc {
+    fn filtered(filter: wgpu::FilterMode, address_mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            anisotropy_clamp: 1,
+            compare: None,
+        }
+    }
+
+    pub fn linear_clamp() -> Self {
+        Self::filtered(wgpu::FilterMode::Linear, wgpu::AddressMode::ClampToEdge)
+    }
+
+    pub fn linear_repeat() -> Self {
+        Self::filtered(wgpu::FilterMode::Linear, wgpu::AddressMode::Repeat)
+    }
+
+    pub fn linear_mirror() -> Self {
+        Self::filtered(wgpu::FilterMode::Linear, wgpu::AddressMode::MirrorRepeat)
+    }
+
+    pub fn nearest_clamp() -> Self {
+        Self::filtered(wgpu::FilterMode::Nearest, wgpu::AddressMode::ClampToEdge)
+    }
+
+    pub fn nearest_repeat() -> Self {
+        Self::filtered(wgpu::FilterMode::Nearest, wgpu::AddressMode::Repeat)
+    }
+
+    pub fn nearest_mirror() -> Self {
+        Self::filtered(wgpu::FilterMode::Nearest, wgpu::AddressMode::MirrorRepeat)
+    }
+
+    /// Trilinear repeating sampler with anisotropic filtering, `max_anisotropy` is clamped to 1..=16
+    pub fn anisotropic(max_anisotropy: u16) -> Self {
+        Self {
+            anisotropy_clamp: max_anisotropy.clamp(1, 16),
+            ..Self::linear_repeat()
+        }
+    }
+
+    /// Linear comparison sampler for shadow maps, sampled with `textureSampleCompare`
+    pub fn shadow() -> Self {
+        Self {
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Self::linear_clamp()
+        }
+    }
+
+    pub fn with_address_mode(mut self, address_mode: wgpu::AddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    /// The sampler binding type a layout needs for this sampler
+    pub fn binding_type(&self) -> wgpu::SamplerBindingType {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter].contains(&wgpu::FilterMode::Linear);
+        match self.compare {
+            Some(_) => wgpu::SamplerBindingType::Comparison,
+            None if linear => wgpu::SamplerBindingType::Filtering,
+            None => wgpu::SamplerBindingType::NonFiltering,
+        }
+    }
+
+    /// Check that a texture with `sample_type` can be sampled with this sampler,
+    /// returning a description of the problem if it can't
+    pub fn check_compatible(&self, sample_type: wgpu::TextureSampleType) -> Result<(), String> {
+        use wgpu::TextureSampleType as T;
+        match (self.binding_type(), sample_type) {
+            (_, T::Uint | T::Sint) => Err(format!("integer textures can't be sampled, read them with textureLoad instead, got {sample_type:?}")),
+            (wgpu::SamplerBindingType::Comparison, T::Depth) => Ok(()),
+            (wgpu::SamplerBindingType::Comparison, _) => Err(format!("comparison samplers need a depth texture, got {sample_type:?}")),
+            (wgpu::SamplerBindingType::Filtering, T::Float { filterable: false }) => Err(
+                "filtering sampler used with an unfilterable float texture (32 bit float formats need FLOAT32_FILTERABLE), use a nearest sampler".into()
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: self.anisotropy_clamp,
+            compare: self.compare,
+            ..Default::default()
+        }
+    }
+}
+
+impl std::ops::Deref for Sampler {
+    type Target = wgpu::Sampler;
+
+    fn deref(&self) -> &Self::Target {
+        &self.raw
+    }
+}
+
+impl Gpu {
+    /// Get a sampler matching `desc`, reusing one from `resources` if it was created before
+    pub fn new_sampler(&self, resources: &mut ResourceManager, desc: SamplerDesc) -> Sampler {
+        resources.samplers.entry(desc)
+            .or_insert_with(|| Sampler {
+                raw: self.device.create_sampler(&desc.descriptor()),
+                desc,
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_type_follows_filters_and_compare() {
+        assert_eq!(SamplerDesc::linear_clamp().binding_type(), wgpu::SamplerBindingType::Filtering);
+        assert_eq!(SamplerDesc::nearest_repeat().binding_type(), wgpu::SamplerBindingType::NonFiltering);
+        assert_eq!(SamplerDesc::shadow().binding_type(), wgpu::SamplerBindingType::Comparison);
+
+        // any linear filter makes it a filtering sampler
+        let mipmapped = SamplerDesc { mipmap_filter: wgpu::FilterMode::Linear, ..SamplerDesc::nearest_clamp() };
+        assert_eq!(mipmapped.binding_type(), wgpu::SamplerBindingType::Filtering);
+        assert_eq!(SamplerDesc::anisotropic(64).anisotropy_clamp, 16);
+    }
+
+    #[test]
+    fn samplers_match_texture_sample_types() {
+        use wgpu::TextureSampleType as T;
+        let filterable = T::Float { filterable: true };
+        let unfilterable = T::Float { filterable: false };
+        let cases = [
+            (SamplerDesc::linear_clamp(), [true, false, true, false, false]),
+            (SamplerDesc::nearest_clamp(), [true, true, true, false, false]),
+            (SamplerDesc::shadow(), [false, false, true, false, false]),
+        ];
+        for (sampler, expected) in cases {
+            let results = [filterable, unfilterable, T::Depth, T::Uint, T::Sint].map(|ty| sampler.check_compatible(ty).is_ok());
+            assert_eq!(results, expected, "{:?}", sampler.binding_type());
+        }
+
+        assert!(SamplerDesc::nearest_clamp().check_compatible(T::Uint).unwrap_err().contains("textureLoad"));
+        assert!(SamplerDesc::shadow().check_compatible(filterable).unwrap_err().contains("need a depth texture"));
+    }
+}
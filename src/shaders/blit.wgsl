@@ -0,0 +1,63 @@
+// Copies a rectangle of one texture into the viewport of a render target, converting format on the way
+// sRGB decoding and encoding happen in the texture views, so the shader only ever sees linear values
+
+struct Params {
+    // source rectangle in texels
+    src_origin: vec2<f32>,
+    src_size: vec2<f32>,
+    // output channel i reads input channel swizzle[i], 4 is constant 0 and 5 is constant 1
+    swizzle: vec4<u32>,
+    // 1 for bilinear filtering, 0 for nearest
+    linear: u32,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn load_clamped(p: vec2<i32>) -> vec4<f32> {
+    let lo = vec2<i32>(params.src_origin);
+    let hi = vec2<i32>(params.src_origin + params.src_size) - 1;
+    return textureLoad(source, clamp(p, lo, hi), 0);
+}
+
+fn pick(c: vec4<f32>, channel: u32) -> f32 {
+    switch channel {
+        case 0u, 1u, 2u, 3u: { return c[channel]; }
+        case 4u: { return 0.0; }
+        default: { return 1.0; }
+    }
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pos = params.src_origin + in.uv * params.src_size;
+
+    var color: vec4<f32>;
+    if (params.linear == 1u) {
+        let st = pos - 0.5;
+        let base = vec2<i32>(floor(st));
+        let f = fract(st);
+        let top    = mix(load_clamped(base),                   load_clamped(base + vec2<i32>(1, 0)), f.x);
+        let bottom = mix(load_clamped(base + vec2<i32>(0, 1)), load_clamped(base + vec2<i32>(1, 1)), f.x);
+        color = mix(top, bottom, f.y);
+    } else {
+        color = load_clamped(vec2<i32>(floor(pos)));
+    }
+
+    let s = params.swizzle;
+    return vec4<f32>(pick(color, s.x), pick(color, s.y), pick(color, s.z), pick(color, s.w));
+}
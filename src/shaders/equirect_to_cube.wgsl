@@ -0,0 +1,48 @@
+// Projects an equirectangular image onto the six faces of a cubemap
+// Faces are written in wgpu layer order: +X, -X, +Y, -Y, +Z, -Z
+
+@group(0) @binding(0) var equirect: texture_2d<f32>;
+@group(0) @binding(1) var faces: texture_storage_2d_array<rgba16float, write>;
+
+const PI: f32 = 3.14159265358979;
+
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch face {
+        case 0u: { return vec3<f32>( 1.0,  -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0,  -uv.y,  uv.x); }
+        case 2u: { return vec3<f32>( uv.x,  1.0,   uv.y); }
+        case 3u: { return vec3<f32>( uv.x, -1.0,  -uv.y); }
+        case 4u: { return vec3<f32>( uv.x, -uv.y,  1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
+
+// wraps horizontally and clamps vertically, equirect images are only continuous around the horizon
+fn load_texel(p: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    let x = ((p.x % size.x) + size.x) % size.x;
+    let y = clamp(p.y, 0, size.y - 1);
+    return textureLoad(equirect, vec2<i32>(x, y), 0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let face_size = textureDimensions(faces);
+    if (id.x >= face_size.x || id.y >= face_size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(face_size) * 2.0 - 1.0;
+    let dir = normalize(face_direction(id.z, uv));
+
+    let size = vec2<i32>(textureDimensions(equirect));
+    let polar = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+
+    // manual bilinear filtering, float32 textures are not filterable without an extra feature
+    let st = polar * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(st));
+    let f = fract(st);
+    let top    = mix(load_texel(base, size),                    load_texel(base + vec2<i32>(1, 0), size), f.x);
+    let bottom = mix(load_texel(base + vec2<i32>(0, 1), size),  load_texel(base + vec2<i32>(1, 1), size), f.x);
+
+    textureStore(faces, id.xy, id.z, mix(top, bottom, f.y));
+}
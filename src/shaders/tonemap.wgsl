@@ -0,0 +1,94 @@
+// Fullscreen tone mapping pass from a linear HDR texture to an LDR target
+
+struct Params {
+    // exposure in stops, the input is scaled by 2^exposure before tone mapping
+    exposure: f32,
+    // 0 clamp, 1 reinhard, 2 aces, 3 agx
+    curve: u32,
+    // 1 when the target is not an sRGB format and the shader has to encode the output itself
+    encode_srgb: u32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // one triangle covering the screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn reinhard(c: vec3<f32>) -> vec3<f32> {
+    return c / (1.0 + c);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(c: vec3<f32>) -> vec3<f32> {
+    return clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Minimal AgX by Benjamin Wrensch, the contrast curve is a polynomial fit of the default AgX look
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(c: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * max(c, vec3<f32>(1e-10));
+    v = clamp(log2(v), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    v = outset * v;
+    // AgX output is display encoded, linearize it so sRGB targets don't encode it twice
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(source);
+    let texel = min(vec2<u32>(in.uv * vec2<f32>(size)), size - 1u);
+    let hdr = textureLoad(source, texel, 0);
+
+    var color = max(hdr.rgb * exp2(params.exposure), vec3<f32>(0.0));
+    switch params.curve {
+        case 1u: { color = reinhard(color); }
+        case 2u: { color = aces(color); }
+        case 3u: { color = agx(color); }
+        default: { color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)); }
+    }
+
+    if (params.encode_srgb == 1u) {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -2,13 +2,18 @@ use std::fmt;
 use std::io;
 use image::ImageError;
 
+use crate::resource::Generation;
+
 pub struct Texture {
     pub label: Option<String>,
     pub dim: wgpu::TextureViewDimension,
     pub raw: wgpu::Texture,
     pub views: Vec<TextureView>,
+    /// Bumped by `Gpu::resize_texture`, shared with every view of this texture
+    pub generation: Generation,
 }
 
+#[derive(Clone)]
 pub struct TextureView {
     pub raw: wgpu::TextureView,
     pub format: wgpu::TextureFormat,
@@ -18,6 +23,7 @@ pub struct TextureView {
     pub mip_level_count: Option<u32>,
     pub base_array_layer: u32,
     pub array_layer_count: Option<u32>,
+    pub generation: Generation,
 }
 
 
@@ -37,32 +43,84 @@ impl Texture {
     }
 
     pub fn new_view(&mut self) -> &TextureView {
-        let s;
-        let label = if self.label.is_some() {
-            s = format!("View of {}", self.label.as_ref().unwrap());
-            Some(s.as_str())
-        } else {
-            None
+        let label = self.label.as_ref().map(|l| format!("View of {l}"));
+
+        let desc = wgpu::TextureViewDescriptor {
+            label: label.as_deref(),
+            dimension: Some(self.dim),
+            ..Default::default()
         };
 
-        let mut desc = wgpu::TextureViewDescriptor::default();
-        desc.label = label;
+        self.new_view_from_desc(&desc)
+    }
+
+    /// Create a view of a single mip level, storage texture bindings need one of these
+    pub fn new_mip_view(&mut self, mip_level: u32) -> &TextureView {
+        let label = self.label.as_ref().map(|l| format!("Mip {mip_level} view of {l}"));
 
+        let desc = wgpu::TextureViewDescriptor {
+            label: label.as_deref(),
+            dimension: Some(self.dim),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        };
+
+        self.new_view_from_desc(&desc)
+    }
 
+    /// Find a view created by `new_mip_view`
+    pub fn mip_view(&self, mip_level: u32) -> Option<&TextureView> {
+        self.views.iter().find(|v| v.base_mip_level == mip_level && v.mip_level_count == Some(1))
+    }
+
+    pub fn new_view_from_desc(&mut self, desc: &wgpu::TextureViewDescriptor) -> &TextureView {
         let view = TextureView {
-            raw: self.raw.create_view(&desc),
-            format: self.raw.format(),
-            dimension: wgpu::TextureViewDimension::D2,
-            aspect: wgpu::TextureAspect::All,
-            base_mip_level: 0,
-            mip_level_count: None,
-            base_array_layer: 0,
-            array_layer_count: None,
+            raw: self.raw.create_view(desc),
+            format: desc.format.unwrap_or(self.raw.format()),
+            dimension: desc.dimension.unwrap_or(self.dim),
+            aspect: desc.aspect,
+            base_mip_level: desc.base_mip_level,
+            mip_level_count: desc.mip_level_count,
+            base_array_layer: desc.base_array_layer,
+            array_layer_count: desc.array_layer_count,
+            generation: self.generation.clone(),
         };
 
         self.views.push(view);
         self.views.last().unwrap()
     }
+
+    /// Swap in a new GPU texture, recreating every view with the same range (clamped to the new mip count)
+    /// and bumping the generation so bind groups using the old one report themselves stale
+    pub(crate) fn replace_raw(&mut self, raw: wgpu::Texture) {
+        let had_default_label = self.label.as_deref() == Some(self.default_label().as_str());
+        self.raw = raw;
+        if had_default_label {
+            self.label = Some(self.default_label());
+        }
+
+        let mip_count = self.raw.mip_level_count();
+        let old_views = std::mem::take(&mut self.views);
+        for old in old_views {
+            let base_mip_level = old.base_mip_level.min(mip_count - 1);
+            let label = self.label.as_ref().map(|l| format!("View of {l}"));
+            let desc = wgpu::TextureViewDescriptor {
+                label: label.as_deref(),
+                format: Some(old.format),
+                dimension: Some(old.dimension),
+                aspect: old.aspect,
+                base_mip_level,
+                mip_level_count: old.mip_level_count.map(|n| n.min(mip_count - base_mip_level)),
+                base_array_layer: old.base_array_layer,
+                array_layer_count: old.array_layer_count,
+                ..Default::default()
+            };
+            self.new_view_from_desc(&desc);
+        }
+
+        self.generation.bump();
+    }
     
 }
 
@@ -84,7 +142,7 @@ impl std::ops::Deref for TextureView {
 
 
 impl TextureView {
-    pub fn attachment(&self) -> wgpu::RenderPassColorAttachment {
+    pub fn attachment(&self) -> wgpu::RenderPassColorAttachment<'_> {
         wgpu::RenderPassColorAttachment {
             view: &self.raw,
             resolve_target: None,
@@ -95,6 +153,32 @@ impl TextureView {
             depth_slice: None,
         }
     }
+
+    /// Attachment for a multisampled view that resolves into `resolve_target` (the surface view or a single sampled texture).
+    /// The multisampled contents are discarded after the resolve
+    pub fn resolve_attachment<'a>(&'a self, resolve_target: &'a TextureView) -> wgpu::RenderPassColorAttachment<'a> {
+        wgpu::RenderPassColorAttachment {
+            view: &self.raw,
+            resolve_target: Some(&resolve_target.raw),
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Discard,
+            },
+            depth_slice: None,
+        }
+    }
+
+    /// Depth attachment cleared to 1.0, stencil is left untouched
+    pub fn depth_attachment(&self) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+        wgpu::RenderPassDepthStencilAttachment {
+            view: &self.raw,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: wgpu::StoreOp::Store,
+            }),
+            stencil_ops: None,
+        }
+    }
 }
 
 
@@ -0,0 +1,116 @@
+use image::{imageops::FilterType, DynamicImage};
+use wgpu::util::DeviceExt;
+
+use crate::{decode::{decode_image_file, is_high_precision, to_f16_bytes}, gpu::Gpu, texture::{Texture, TextureError}};
+
+/// Number of mip levels in a full chain down to 1x1
+pub fn full_mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Every mip of every image resized to `width` by `height`, layer major, the order create_texture_with_data calls
+/// LayerMajor. Texels are `Rgba16Float` when `high_precision`, `Rgba8UnormSrgb` otherwise
+fn layer_major_data(images: &[DynamicImage], width: u32, height: u32, mip_level_count: u32, high_precision: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    for image in images {
+        for mip in 0..mip_level_count {
+            let (mip_width, mip_height) = ((width >> mip).max(1), (height >> mip).max(1));
+            let level = if (image.width(), image.height()) == (mip_width, mip_height) {
+                image.clone()
+            } else {
+                image.resize_exact(mip_width, mip_height, FilterType::Triangle)
+            };
+
+            if high_precision {
+                data.extend(to_f16_bytes(level.to_rgba32f().into_raw().into_iter()));
+            } else {
+                data.extend(level.to_rgba8().into_raw());
+            }
+        }
+    }
+    data
+}
+
+impl Gpu {
+    /// Load images into the layers of one `D2Array` texture with a full mip chain generated on the CPU
+    ///
+    /// Images that differ in size from the first one are resized to match it. If any image has more than 8 bits
+    /// per channel the array is `Rgba16Float`, otherwise it is `Rgba8UnormSrgb`
+    pub fn new_texture_array_from_files(&self, paths: &[&str]) -> Result<Texture, TextureError> {
+        if paths.is_empty() {
+            return Err(TextureError::Other("texture arrays need at least one layer".into()));
+        }
+
+        let images = paths.iter()
+            .map(|path| decode_image_file(path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (width, height) = (images[0].width(), images[0].height());
+        let mip_level_count = full_mip_count(width, height);
+        let high_precision = images.iter().any(is_high_precision);
+        let format = if high_precision { wgpu::TextureFormat::Rgba16Float } else { wgpu::TextureFormat::Rgba8UnormSrgb };
+
+        let data = layer_major_data(&images, width, height, mip_level_count, high_precision);
+
+        let desc = wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: images.len() as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+
+        let mut tex = Texture {
+            raw: self.device.create_texture_with_data(&self.queue, &desc, wgpu::util::TextureDataOrder::LayerMajor, &data),
+            label: None,
+            dim: wgpu::TextureViewDimension::D2Array,
+            views: Vec::new(),
+            generation: Default::default(),
+        };
+        tex.label = Some(tex.default_label());
+        tex.new_view();
+        Ok(tex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_count_reaches_1x1() {
+        assert_eq!(full_mip_count(1, 1), 1);
+        assert_eq!(full_mip_count(256, 256), 9);
+        assert_eq!(full_mip_count(300, 17), 9);
+        assert_eq!(full_mip_count(1, 5), 3);
+        assert_eq!(full_mip_count(0, 0), 1);
+        assert_eq!(full_mip_count(0, 64), 7);
+    }
+
+    #[test]
+    fn layers_hold_every_mip_in_order() {
+        let solid = |color: [u8; 4], size: u32| DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(size, size / 2, image::Rgba(color)));
+        // the second layer is resized to match the first
+        let images = [solid([255, 0, 0, 255], 8), solid([0, 0, 255, 255], 16)];
+        let mips = full_mip_count(8, 4);
+        assert_eq!(mips, 4);
+
+        // 8x4, 4x2, 2x1 and 1x1 per layer
+        let texels_per_layer = (8 * 4 + 4 * 2 + 2 + 1) as usize;
+        let data = layer_major_data(&images, 8, 4, mips, false);
+        assert_eq!(data.len(), 2 * texels_per_layer * 4);
+        assert_eq!(data[..4], [255, 0, 0, 255]);
+        assert_eq!(data[(texels_per_layer - 1) * 4..texels_per_layer * 4], [255, 0, 0, 255]);
+        assert_eq!(data[texels_per_layer * 4..texels_per_layer * 4 + 4], [0, 0, 255, 255]);
+
+        let data = layer_major_data(&images, 8, 4, mips, true);
+        assert_eq!(data.len(), 2 * texels_per_layer * 8);
+    }
+}
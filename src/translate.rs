@@ -0,0 +1,165 @@
+//! GLSL and SPIR-V input, translated to WGSL with naga so they share the WGSL caching, reflection and diagnostics
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::diagnostics::{Diagnostic, DiagnosticLabel};
+use crate::preprocess::{normalize_path, DependencyGraph, PreprocessedShader, ShaderDefs, SourceMap};
+
+/// What a shader file is written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    Wgsl,
+    /// GLSL for a single stage, its entry point is `main`
+    Glsl(naga::ShaderStage),
+    SpirV,
+}
+
+impl ShaderLanguage {
+    /// Guess from the extension: `.vert`, `.frag` and `.comp` are GLSL, `.spv` is SPIR-V and anything else is WGSL
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => ShaderLanguage::Glsl(naga::ShaderStage::Vertex),
+            Some("frag") => ShaderLanguage::Glsl(naga::ShaderStage::Fragment),
+            Some("comp") => ShaderLanguage::Glsl(naga::ShaderStage::Compute),
+            Some("spv") => ShaderLanguage::SpirV,
+            _ => ShaderLanguage::Wgsl,
+        }
+    }
+}
+
+/// Translate a GLSL or SPIR-V file to WGSL. `defs` become `#define`s for GLSL and are ignored for SPIR-V.
+///
+/// The result has no imports, GLSL `#include` isn't supported. Errors point into the GLSL source, SPIR-V errors have no location.
+/// WGSL is an error too, it goes through `preprocess::preprocess_with_defs` instead
+pub fn translate(path: &str, language: ShaderLanguage, bytes: &[u8], defs: &ShaderDefs) -> Result<PreprocessedShader, Diagnostic> {
+    let path = normalize_path(path);
+    let error = |message: String| Diagnostic { message, labels: Vec::new(), notes: Vec::new() };
+
+    let (module, source) = match language {
+        ShaderLanguage::Wgsl => return Err(error(format!("{path} is WGSL, preprocess it instead of translating it"))),
+        ShaderLanguage::Glsl(stage) => {
+            let source = std::str::from_utf8(bytes).map_err(|_| error(format!("{path} isn't valid UTF-8")))?;
+            let mut options = naga::front::glsl::Options::from(stage);
+            options.defines.extend(defs.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+
+            let source_map = SourceMap::identity(&path, source);
+            let module = naga::front::glsl::Frontend::default().parse(&options, source).map_err(|e| {
+                let mut errors = e.errors.iter();
+                let first = errors.next().map_or_else(|| "invalid GLSL".to_string(), |e| e.kind.to_string());
+                Diagnostic {
+                    message: first,
+                    labels: e.errors.iter()
+                        .filter_map(|e| DiagnosticLabel::from_span(source, &source_map, e.meta, &e.kind.to_string()))
+                        .collect(),
+                    notes: errors.map(|e| e.kind.to_string()).collect(),
+                }
+            })?;
+            (module, Some((source, source_map)))
+        },
+        ShaderLanguage::SpirV => {
+            let module = naga::front::spv::parse_u8_slice(bytes, &naga::front::spv::Options::default())
+                .map_err(|e| error(format!("invalid SPIR-V in {path}: {e}")))?;
+            (module, None)
+        },
+    };
+
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| match &source {
+            Some((source, source_map)) => Diagnostic::from_validation_error(&e, source, source_map),
+            None => Diagnostic::from_validation_error(&e, "", &SourceMap::default()),
+        })?;
+    let code = naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+        .map_err(|e| error(format!("failed to translate {path} to WGSL: {e}")))?;
+
+    // errors in the translated code can't be mapped back to the original, so point at the WGSL itself
+    let source_map = SourceMap::identity(&format!("{path} (as WGSL)"), &code);
+    Ok(PreprocessedShader {
+        code,
+        graph: DependencyGraph {
+            root: path.clone(),
+            imports: BTreeMap::from([(path, Vec::new())]),
+        },
+        defs: defs.clone(),
+        source_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPUTE: &str = "#version 450
+layout(local_size_x = WORKGROUP_SIZE) in;
+
+layout(set = 0, binding = 0) buffer Values {
+    float values[];
+};
+
+void main() {
+    values[gl_GlobalInvocationID.x] *= 2.0;
+}
+";
+
+    fn wgsl_entry_points(code: &str) -> Vec<(naga::ShaderStage, [u32; 3])> {
+        let module = naga::front::wgsl::parse_str(code).unwrap();
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(&module)
+            .unwrap();
+        module.entry_points.iter().map(|ep| (ep.stage, ep.workgroup_size)).collect()
+    }
+
+    #[test]
+    fn guesses_the_language_from_the_extension() {
+        assert_eq!(ShaderLanguage::from_path("blur.comp"), ShaderLanguage::Glsl(naga::ShaderStage::Compute));
+        assert_eq!(ShaderLanguage::from_path("shaders/sky.frag"), ShaderLanguage::Glsl(naga::ShaderStage::Fragment));
+        assert_eq!(ShaderLanguage::from_path("mesh.spv"), ShaderLanguage::SpirV);
+        assert_eq!(ShaderLanguage::from_path("mesh.wgsl"), ShaderLanguage::Wgsl);
+    }
+
+    #[test]
+    fn glsl_compute_translates_to_wgsl() {
+        let mut defs = ShaderDefs::new();
+        defs.define("WORKGROUP_SIZE", "64");
+        let shader = translate("shaders/./double.comp", ShaderLanguage::Glsl(naga::ShaderStage::Compute), COMPUTE.as_bytes(), &defs).unwrap();
+
+        assert_eq!(wgsl_entry_points(&shader.code), [(naga::ShaderStage::Compute, [64, 1, 1])]);
+        assert_eq!(shader.graph.root, "shaders/double.comp");
+        assert_eq!(shader.defs, defs);
+        assert_eq!(shader.source_map.locate(1), Some(("shaders/double.comp (as WGSL)", 1)));
+    }
+
+    #[test]
+    fn defines_reach_the_glsl_frontend() {
+        let mut defs = ShaderDefs::new();
+        defs.define("WORKGROUP_SIZE", "8");
+        let shader = translate("double.comp", ShaderLanguage::Glsl(naga::ShaderStage::Compute), COMPUTE.as_bytes(), &defs).unwrap();
+        assert_eq!(wgsl_entry_points(&shader.code), [(naga::ShaderStage::Compute, [8, 1, 1])]);
+
+        // without the define the layout qualifier names an unknown identifier
+        let stage = ShaderLanguage::Glsl(naga::ShaderStage::Compute);
+        assert!(translate("double.comp", stage, COMPUTE.as_bytes(), &ShaderDefs::new()).is_err());
+    }
+
+    #[test]
+    fn glsl_errors_point_at_the_line() {
+        let source = "#version 450\nlayout(location = 0) out vec4 color;\n\nvoid main() {\n    color = vec4(1.0) +;\n}\n";
+        let diagnostic = translate("broken.frag", ShaderLanguage::Glsl(naga::ShaderStage::Fragment), source.as_bytes(), &ShaderDefs::new()).unwrap_err();
+
+        let label = &diagnostic.labels[0];
+        assert_eq!((label.file.as_str(), label.line), ("broken.frag", 5));
+        assert_eq!(label.source_line.as_deref(), Some("    color = vec4(1.0) +;"));
+    }
+
+    #[test]
+    fn invalid_input_is_an_error() {
+        let diagnostic = translate("garbage.spv", ShaderLanguage::SpirV, &[0xde, 0xad, 0xbe, 0xef, 0, 1], &ShaderDefs::new()).unwrap_err();
+        assert!(diagnostic.message.starts_with("invalid SPIR-V in garbage.spv"), "{}", diagnostic.message);
+        assert!(translate("empty.spv", ShaderLanguage::SpirV, &[], &ShaderDefs::new()).is_err());
+
+        assert!(translate("bad.frag", ShaderLanguage::Glsl(naga::ShaderStage::Fragment), &[0xff, 0xfe], &ShaderDefs::new()).is_err());
+        let diagnostic = translate("main.wgsl", ShaderLanguage::Wgsl, b"fn main() {}", &ShaderDefs::new()).unwrap_err();
+        assert_eq!(diagnostic.message, "main.wgsl is WGSL, preprocess it instead of translating it");
+    }
+}
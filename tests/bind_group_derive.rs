@@ -0,0 +1,96 @@
+use hb_gpu::bindgroup::{AsBindGroup, BindGroup};
+use hb_gpu::prelude::wgpu;
+use hb_gpu::{buffer::Buffer, sampler::Sampler, texture::Texture};
+
+#[allow(dead_code)]
+#[derive(BindGroup)]
+struct Material<'a> {
+    #[uniform(0, ty = "MaterialParams")]
+    params: Buffer,
+    #[texture(1, fragment)]
+    albedo: &'a Texture,
+    #[sampler(2, fragment)]
+    albedo_sampler: Sampler,
+    #[storage(4, read_only)]
+    instances: Buffer,
+    #[storage(3)]
+    counters: Buffer,
+    #[texture(5, dim = "cube", sample = "depth")]
+    shadow: Texture,
+    #[sampler(6, comparison)]
+    shadow_sampler: Sampler,
+    #[texture(7, sample = "u32", multisampled)]
+    ids: Texture,
+    unbound: u32,
+}
+
+#[test]
+fn layout_entries_are_sorted_by_binding() {
+    let layout = Material::layout_entries();
+    let bindings: Vec<u32> = layout.entries().iter().map(|e| e.binding).collect();
+    assert_eq!(bindings, [0, 1, 2, 3, 4, 5, 6, 7]);
+}
+
+#[test]
+fn buffer_entries() {
+    let layout = Material::layout_entries();
+    let all = wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE;
+    let buffer = |binding: u32| {
+        let entry = layout.get(binding).unwrap();
+        let wgpu::BindingType::Buffer { ty, .. } = entry.ty else { panic!("binding {binding} isn't a buffer") };
+        (ty, entry.visibility)
+    };
+
+    assert_eq!(buffer(0), (wgpu::BufferBindingType::Uniform, all));
+    assert_eq!(buffer(4), (wgpu::BufferBindingType::Storage { read_only: true }, all));
+    // writable storage isn't allowed in vertex shaders
+    assert_eq!(buffer(3), (
+        wgpu::BufferBindingType::Storage { read_only: false },
+        wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+    ));
+}
+
+#[test]
+fn texture_and_sampler_entries() {
+    let layout = Material::layout_entries();
+    let ty = |binding: u32| layout.get(binding).unwrap().ty;
+
+    assert_eq!(layout.get(1).unwrap().visibility, wgpu::ShaderStages::FRAGMENT);
+    assert_eq!(ty(1), wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        view_dimension: wgpu::TextureViewDimension::D2,
+        multisampled: false,
+    });
+    assert_eq!(ty(5), wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Depth,
+        view_dimension: wgpu::TextureViewDimension::Cube,
+        multisampled: false,
+    });
+    assert_eq!(ty(7), wgpu::BindingType::Texture {
+        sample_type: wgpu::TextureSampleType::Uint,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        multisampled: true,
+    });
+    assert_eq!(ty(2), wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering));
+    assert_eq!(ty(6), wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison));
+}
+
+#[test]
+fn wgsl_declarations() {
+    assert_eq!(Material::wgsl(2), "\
+@group(2) @binding(0) var<uniform> params: MaterialParams;
+@group(2) @binding(1) var albedo: texture_2d<f32>;
+@group(2) @binding(2) var albedo_sampler: sampler;
+@group(2) @binding(4) var<storage, read> instances: Instances;
+@group(2) @binding(3) var<storage, read_write> counters: Counters;
+@group(2) @binding(5) var shadow: texture_depth_cube;
+@group(2) @binding(6) var shadow_sampler: sampler_comparison;
+@group(2) @binding(7) var ids: texture_multisampled_2d<u32>;
+");
+}
+
+#[test]
+fn compile_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/bind_group/*.rs");
+}
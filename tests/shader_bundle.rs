@@ -0,0 +1,26 @@
+use hb_gpu::bundle::{include_wgsl_bundle, ShaderBundle};
+
+const LIGHTS: ShaderBundle = include_wgsl_bundle!("tests/shaders/lights.wgsl");
+
+#[test]
+fn imports_are_flattened() {
+    assert!(!LIGHTS.code.contains("@import"));
+    assert!(LIGHTS.code.contains("struct Light {"));
+    assert!(LIGHTS.code.contains("fn fs_main()"));
+}
+
+#[test]
+fn lists_every_file() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/shaders/lights.wgsl");
+    assert_eq!(LIGHTS.path, root);
+    assert_eq!(LIGHTS.files.len(), 2);
+    assert!(LIGHTS.files.contains(&root));
+    assert!(LIGHTS.files.iter().any(|file| file.ends_with("/tests/shaders/common.wgsl")));
+}
+
+#[test]
+fn source_matches_the_embedded_code() {
+    // nothing changed on disk since the build
+    assert_eq!(LIGHTS.source().unwrap(), LIGHTS.code);
+    assert_eq!(LIGHTS.descriptor().label, Some(LIGHTS.path));
+}
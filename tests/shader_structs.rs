@@ -0,0 +1,28 @@
+mod lights {
+    hb_gpu::bundle::include_wgsl_structs!("tests/shaders/lights.wgsl");
+}
+
+#[test]
+fn structs_match_the_wgsl_layout() {
+    assert_eq!(std::mem::size_of::<lights::Light>(), 48);
+    assert_eq!(std::mem::offset_of!(lights::Light, intensity), 12);
+    assert_eq!(std::mem::offset_of!(lights::Light, color), 16);
+    assert_eq!(std::mem::offset_of!(lights::Light, range), 32);
+    // the runtime-sized array isn't part of the struct, only the header before it
+    assert_eq!(std::mem::offset_of!(lights::Lights, count), 0);
+}
+
+#[test]
+fn structs_are_pod_and_default_to_zero() {
+    let light = lights::Light { intensity: 2.0, color: [1.0, 0.5, 0.25], ..Default::default() };
+    let bytes: &[u8] = bytemuck::bytes_of(&light);
+    assert_eq!(bytes.len(), 48);
+    assert_eq!(bytemuck::pod_read_unaligned::<f32>(&bytes[12..16]), 2.0);
+    assert!(bytes[28..32].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn binding_constants() {
+    assert_eq!((lights::LIGHT_GROUP, lights::LIGHT_BINDING), (0, 0));
+    assert_eq!((lights::LIGHTS_GROUP, lights::LIGHTS_BINDING), (1, 2));
+}
@@ -0,0 +1,6 @@
+struct Light {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    range: vec2<f32>,
+}
@@ -0,0 +1,14 @@
+@import "common.wgsl";
+
+struct Lights {
+    count: u32,
+    items: array<Light>,
+}
+
+@group(0) @binding(0) var<uniform> light: Light;
+@group(1) @binding(2) var<storage, read> lights: Lights;
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(light.color * light.intensity, f32(lights.count));
+}
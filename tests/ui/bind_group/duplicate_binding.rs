@@ -0,0 +1,11 @@
+use hb_gpu::{bindgroup::BindGroup, buffer::Buffer};
+
+#[derive(BindGroup)]
+struct Camera {
+    #[uniform(0)]
+    view: Buffer,
+    #[uniform(0)]
+    projection: Buffer,
+}
+
+fn main() {}
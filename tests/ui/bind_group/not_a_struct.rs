@@ -0,0 +1,11 @@
+use hb_gpu::bindgroup::BindGroup;
+
+#[derive(BindGroup)]
+enum Material {
+    Opaque,
+}
+
+#[derive(BindGroup)]
+struct Tuple(u32);
+
+fn main() {}
@@ -0,0 +1,21 @@
+use hb_gpu::{bindgroup::BindGroup, buffer::Buffer, texture::Texture};
+
+#[derive(BindGroup)]
+struct Uniform {
+    #[uniform(0, read_only)]
+    camera: Buffer,
+}
+
+#[derive(BindGroup)]
+struct Dimension {
+    #[texture(0, dim = "4d")]
+    volume: Texture,
+}
+
+#[derive(BindGroup)]
+struct SampleType {
+    #[texture(0, sample = "f64")]
+    volume: Texture,
+}
+
+fn main() {}